@@ -0,0 +1,130 @@
+use quicksilver::geom::Vector;
+use serde::{Deserialize, Serialize};
+
+/// Entities that move around the board on their own.
+pub trait Mobile {
+    fn position_mut(&mut self) -> &mut Vector;
+    fn direction_mut(&mut self) -> &mut Vector;
+}
+
+/// What happens to entities crossing the edge of the board.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BoundaryPolicy {
+    /// stop at the edge
+    Clamp,
+    /// reflect off the edge, reversing the direction
+    Bounce,
+    /// come back on the opposite side, the board is a torus
+    Wrap,
+}
+
+impl Default for BoundaryPolicy {
+    fn default() -> Self {
+        crate::config::BOUNDARY_POLICY
+    }
+}
+
+impl BoundaryPolicy {
+    /// Brings `entity` back inside a board spanning from zero to `game_size`.
+    pub fn apply(self, entity: &mut impl Mobile, game_size: Vector) {
+        let (x, dx) = self.apply_axis(entity.position_mut().x, entity.direction_mut().x, game_size.x);
+        let (y, dy) = self.apply_axis(entity.position_mut().y, entity.direction_mut().y, game_size.y);
+        *entity.position_mut() = Vector::new(x, y);
+        *entity.direction_mut() = Vector::new(dx, dy);
+    }
+
+    /// Shortest way from `from` to `to`, across the edge when the board wraps around.
+    pub fn offset(self, from: Vector, to: Vector, game_size: Vector) -> Vector {
+        let difference = to - from;
+        match self {
+            BoundaryPolicy::Wrap => Vector::new(
+                Self::shortest(difference.x, game_size.x),
+                Self::shortest(difference.y, game_size.y),
+            ),
+            BoundaryPolicy::Clamp | BoundaryPolicy::Bounce => difference,
+        }
+    }
+
+    pub fn distance(self, from: Vector, to: Vector, game_size: Vector) -> f32 {
+        self.offset(from, to, game_size).len()
+    }
+
+    /// Brings a point that went past the edge back on the board when it wraps around.
+    pub fn wrapped(self, position: Vector, game_size: Vector) -> Vector {
+        match self {
+            BoundaryPolicy::Wrap => Vector::new(
+                position.x.rem_euclid(game_size.x),
+                position.y.rem_euclid(game_size.y),
+            ),
+            BoundaryPolicy::Clamp | BoundaryPolicy::Bounce => position,
+        }
+    }
+
+    fn shortest(difference: f32, max: f32) -> f32 {
+        let forward = difference.rem_euclid(max);
+        if forward > max / 2. {
+            forward - max
+        } else {
+            forward
+        }
+    }
+
+    fn apply_axis(self, position: f32, direction: f32, max: f32) -> (f32, f32) {
+        if position >= 0. && position <= max {
+            return (position, direction);
+        }
+        match self {
+            BoundaryPolicy::Clamp => (position.max(0.).min(max), direction),
+            BoundaryPolicy::Bounce => {
+                if position < 0. {
+                    ((-position).min(max), direction.abs())
+                } else {
+                    ((2. * max - position).max(0.), -direction.abs())
+                }
+            }
+            BoundaryPolicy::Wrap => (position.rem_euclid(max), direction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_boundary {
+    use super::*;
+    use crate::game::Player;
+
+    fn moved_to(policy: BoundaryPolicy, position: Vector, direction: Vector) -> Player {
+        let mut player = Player::new(1);
+        player.position = position;
+        player.direction = direction;
+        policy.apply(&mut player, Vector::new(100., 100.));
+        player
+    }
+
+    #[test]
+    fn test_policies() {
+        let inside = moved_to(BoundaryPolicy::Bounce, Vector::new(50., 50.), Vector::new(1., 0.));
+        assert_eq!(inside.position, Vector::new(50., 50.));
+
+        let clamped = moved_to(BoundaryPolicy::Clamp, Vector::new(-5., 104.), Vector::new(-1., 1.));
+        assert_eq!(clamped.position, Vector::new(0., 100.));
+        assert_eq!(clamped.direction, Vector::new(-1., 1.));
+
+        let bounced = moved_to(BoundaryPolicy::Bounce, Vector::new(-5., 104.), Vector::new(-1., 1.));
+        assert_eq!(bounced.position, Vector::new(5., 96.));
+        assert_eq!(bounced.direction, Vector::new(1., -1.));
+
+        let wrapped = moved_to(BoundaryPolicy::Wrap, Vector::new(-5., 104.), Vector::new(-1., 1.));
+        assert_eq!(wrapped.position, Vector::new(95., 4.));
+        assert_eq!(wrapped.direction, Vector::new(-1., 1.));
+    }
+
+    #[test]
+    fn test_shortest_way_across_the_edge() {
+        let size = Vector::new(100., 100.);
+        let (from, to) = (Vector::new(95., 50.), Vector::new(5., 40.));
+        assert_eq!(BoundaryPolicy::Clamp.offset(from, to, size), Vector::new(-90., -10.));
+        assert_eq!(BoundaryPolicy::Wrap.offset(from, to, size), Vector::new(10., -10.));
+        assert_eq!(BoundaryPolicy::Wrap.distance(to, from, size), Vector::new(10., 10.).len());
+        assert_eq!(BoundaryPolicy::Wrap.wrapped(Vector::new(105., -5.), size), Vector::new(5., 95.));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::boundary::{BoundaryPolicy, Mobile};
+use crate::game::{Player, PlayerHandle, VectorDef};
+use crate::obstacles::Obstacle;
+use quicksilver::geom::Vector;
+use serde::{Deserialize, Serialize};
+
+/// Identifies a single cell of a player, index 0 is the player's main cell.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellId {
+    pub player: PlayerHandle,
+    pub index: usize,
+}
+
+/// A piece split off a player, it shares the handle, name and color of the player.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Cell {
+    #[serde(with = "VectorDef")]
+    pub position: Vector,
+    pub size: f32,
+    /// velocity left over from the split, per second, fades away with every step
+    #[serde(with = "VectorDef")]
+    pub momentum: Vector,
+    /// game clock from which the cell may merge back into the main one
+    pub merge_at: u32,
+}
+
+impl Cell {
+    pub fn can_merge(&self, game_clock: u32) -> bool {
+        game_clock >= self.merge_at
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+        self.size += amount;
+    }
+
+    /// Moves along the direction of the player, once the merge cooldown is over
+    /// the cell is also pulled back towards the main one at `main`.
+    pub fn advance(
+        &mut self,
+        direction: Vector,
+        main: Vector,
+        game_clock: u32,
+        boundary: BoundaryPolicy,
+        game_size: Vector,
+        dt: f32,
+    ) {
+        let speed = Player::speed_for(self.size);
+        let mut velocity = Player::velocity_along(direction, speed);
+        let towards_main = boundary.offset(self.position, main, game_size);
+        if self.can_merge(game_clock) && towards_main.len2() > 0. {
+            velocity += towards_main.normalize() * speed;
+        }
+        self.position += (velocity + self.momentum) * dt;
+        self.momentum *= crate::config::SPLIT_MOMENTUM_DECAY.powf(dt);
+        if self.momentum.len() < crate::config::MIN_DRIFT_SPEED {
+            self.momentum = Vector::ZERO;
+        }
+        boundary.apply(self, game_size);
+    }
+}
+
+impl Obstacle for Cell {
+    fn radius(&self) -> f32 {
+        self.size
+    }
+    fn center(&self) -> Vector {
+        self.position
+    }
+    fn strength(&self) -> f32 {
+        self.size
+    }
+}
+
+impl Mobile for Cell {
+    fn position_mut(&mut self) -> &mut Vector {
+        &mut self.position
+    }
+    /// the cell is steered by its player, only the momentum bounces off the edges
+    fn direction_mut(&mut self) -> &mut Vector {
+        &mut self.momentum
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+pub const HELP: &str = "\
+prawario backend
+
+USAGE:
+    backend [COMMAND] [OPTIONS]
+
+COMMANDS:
+    serve             runs the game server, the default when no command is given
+    simulate          runs the game without a server and prints statistics
+    replay <FILE>     runs a recorded simulation again, with the seed and settings it was
+                      recorded with, and checks it ends the same way
+    help              prints this message
+
+OPTIONS:
+    -h, --help                 prints this message
+
+SERVE AND SIMULATE OPTIONS:
+    --config <FILE>            reads settings from a .toml or .json file
+    --set <SECTION.NAME=VALUE> overrides a single setting, e.g. --set game.tick_rate=30
+    --seed <N>                 seed of every random decision of the game
+
+SERVE OPTIONS:
+    --bind <IP:PORT>           address to listen on, 0.0.0.0:3030 by default
+    --static-dir <DIR>         directory with the built client, dist/ by default
+
+SIMULATE OPTIONS:
+    --ticks <N>                steps to simulate, 3600 by default
+    --players <N>              bots steering around at random, 10 by default
+    --record <FILE>            saves the run, to be replayed later
+
+Settings can also be overridden with environment variables, PRAWARIO_GAME_TICK_RATE=30
+sets game.tick_rate. Options win over the environment, which wins over the config file.
+
+EXIT CODES:
+    0    success
+    1    the command failed, e.g. a replay ended differently than recorded
+    2    invalid arguments or settings
+";
+
+/// Settings shared by every command.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Common {
+    pub config: Option<PathBuf>,
+    pub overrides: Vec<(String, String)>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulate {
+    pub ticks: u32,
+    pub players: usize,
+    pub record: Option<PathBuf>,
+}
+
+impl Default for Simulate {
+    fn default() -> Self {
+        Self {
+            ticks: 3600,
+            players: 10,
+            record: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Serve(Common),
+    Simulate(Common, Simulate),
+    Replay(PathBuf),
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    UnknownCommand(String),
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue { option: String, value: String },
+    /// `replay` was given no file
+    MissingFile,
+    UnexpectedArgument(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownCommand(command) => write!(f, "unknown command: {}", command),
+            CliError::UnknownOption(option) => write!(f, "unknown option: {}", option),
+            CliError::MissingValue(option) => write!(f, "{} needs a value", option),
+            CliError::InvalidValue { option, value } => {
+                write!(f, "invalid value for {}: {:?}", option, value)
+            }
+            CliError::MissingFile => write!(f, "replay needs a file to replay"),
+            CliError::UnexpectedArgument(argument) => {
+                write!(f, "unexpected argument: {}", argument)
+            }
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: String) -> Result<T, CliError> {
+    value.parse().map_err(|_e| CliError::InvalidValue {
+        option: option.to_string(),
+        value,
+    })
+}
+
+/// Reads the arguments, without the name of the program.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, CliError> {
+    let mut args = args.into_iter().peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some("serve") | Some("simulate") | Some("replay") | Some("help") => args.next(),
+        Some(other) if !other.starts_with('-') => {
+            return Err(CliError::UnknownCommand(other.to_string()))
+        }
+        _ => None,
+    };
+    let command = command.unwrap_or_else(|| "serve".to_string());
+
+    let mut common = Common::default();
+    let mut simulate = Simulate::default();
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        if !arg.starts_with('-') {
+            match (command.as_str(), &file) {
+                ("replay", None) => file = Some(PathBuf::from(arg)),
+                _ => return Err(CliError::UnexpectedArgument(arg)),
+            }
+            continue;
+        }
+        let known = match (command.as_str(), arg.as_str()) {
+            (_, "--config") | (_, "--set") | (_, "--seed") => true,
+            ("serve", "--bind") | ("serve", "--static-dir") => true,
+            ("simulate", "--ticks") | ("simulate", "--players") | ("simulate", "--record") => true,
+            _ => false,
+        };
+        if !known || command == "replay" {
+            return Err(CliError::UnknownOption(arg));
+        }
+        let value = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+        match arg.as_str() {
+            "--config" => common.config = Some(PathBuf::from(value)),
+            "--set" => match value.split_once('=') {
+                Some((key, setting)) => common
+                    .overrides
+                    .push((key.to_string(), setting.to_string())),
+                None => {
+                    return Err(CliError::InvalidValue {
+                        option: arg.clone(),
+                        value: value.clone(),
+                    })
+                }
+            },
+            "--seed" => common.seed = Some(parse_value(&arg, value)?),
+            "--bind" => {
+                let address: SocketAddr = parse_value(&arg, value)?;
+                // the same overrides as --set, so that the precedence stays the same
+                common
+                    .overrides
+                    .push(("server.bind".to_string(), address.ip().to_string()));
+                common
+                    .overrides
+                    .push(("server.port".to_string(), address.port().to_string()));
+            }
+            "--static-dir" => common
+                .overrides
+                .push(("server.static_dir".to_string(), value)),
+            "--ticks" => simulate.ticks = parse_value(&arg, value)?,
+            "--players" => simulate.players = parse_value(&arg, value)?,
+            "--record" => simulate.record = Some(PathBuf::from(value)),
+            _ => unreachable!("only known options get this far"),
+        }
+    }
+
+    match command.as_str() {
+        "serve" => Ok(Command::Serve(common)),
+        "simulate" => Ok(Command::Simulate(common, simulate)),
+        "replay" => file.map(Command::Replay).ok_or(CliError::MissingFile),
+        _ => Ok(Command::Help),
+    }
+}
+
+#[cfg(test)]
+mod test_cli {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_serve_is_the_default() {
+        assert_eq!(parse(args("")), Ok(Command::Serve(Common::default())));
+        let expected = Common {
+            config: Some(PathBuf::from("server.toml")),
+            overrides: vec![
+                ("game.tick_rate".to_string(), "30".to_string()),
+                ("server.bind".to_string(), "127.0.0.1".to_string()),
+                ("server.port".to_string(), "8080".to_string()),
+                ("server.static_dir".to_string(), "public".to_string()),
+            ],
+            seed: Some(7),
+        };
+        let line = "--config server.toml --set game.tick_rate=30 --bind 127.0.0.1:8080 --static-dir public --seed 7";
+        assert_eq!(parse(args(line)), Ok(Command::Serve(expected.clone())));
+        assert_eq!(parse(args(&format!("serve {}", line))), Ok(Command::Serve(expected)));
+    }
+
+    #[test]
+    fn test_other_commands() {
+        assert_eq!(
+            parse(args("simulate --ticks 100 --players 3 --record run.json")),
+            Ok(Command::Simulate(
+                Common::default(),
+                Simulate {
+                    ticks: 100,
+                    players: 3,
+                    record: Some(PathBuf::from("run.json")),
+                }
+            ))
+        );
+        assert_eq!(parse(args("replay run.json")), Ok(Command::Replay(PathBuf::from("run.json"))));
+        assert_eq!(parse(args("help")), Ok(Command::Help));
+        assert_eq!(parse(args("simulate --help")), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert_eq!(parse(args("fight")), Err(CliError::UnknownCommand("fight".to_string())));
+        assert_eq!(parse(args("--port 80")), Err(CliError::UnknownOption("--port".to_string())));
+        assert_eq!(
+            parse(args("simulate --bind 0.0.0.0:80")),
+            Err(CliError::UnknownOption("--bind".to_string()))
+        );
+        assert_eq!(parse(args("serve --seed")), Err(CliError::MissingValue("--seed".to_string())));
+        assert!(matches!(parse(args("serve --bind 80")), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(args("serve --set tick_rate")), Err(CliError::InvalidValue { .. })));
+        assert_eq!(parse(args("replay")), Err(CliError::MissingFile));
+        // a replay runs with what was recorded
+        assert_eq!(
+            parse(args("replay run.json --seed 1")),
+            Err(CliError::UnknownOption("--seed".to_string()))
+        );
+        assert_eq!(
+            parse(args("replay a.json b.json")),
+            Err(CliError::UnexpectedArgument("b.json".to_string()))
+        );
+    }
+}
@@ -1,24 +1,378 @@
+use crate::game::Camera;
 use crate::game::Game;
 use crate::game::PlayerHandle;
 use crate::game::PlayerInput;
+use crate::rendering::Rgb;
+use crate::snapshot::Snapshot;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
+/// Inputs of a single client frame.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ClientMessage {
+pub struct InputMessage {
     pub inputs: Vec<PlayerInput>,
     pub player_handle: PlayerHandle,
+    /// increases with every message, the server echoes the last one it applied
+    pub sequence: u32,
+    /// server tick the client expects the inputs to apply from, its prediction replays them from there
+    pub game_clock: u32,
 }
 
-impl ClientMessage {
-    pub fn new(inputs: Vec<PlayerInput>, player_handle: PlayerHandle) -> Self {
+impl InputMessage {
+    pub fn new(inputs: Vec<PlayerInput>, player_handle: PlayerHandle, sequence: u32) -> Self {
         Self {
             inputs,
             player_handle,
+            sequence,
+            game_clock: 0,
         }
     }
+
+    /// Whether the message claims to steer anyone but `owner`.
+    pub fn impersonates(&self, owner: PlayerHandle) -> bool {
+        self.player_handle != owner || self.inputs.iter().any(|(handle, _input)| *handle != owner)
+    }
+}
+
+/// Confirms that the client received the snapshot taken at `game_clock`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SnapshotAck {
+    pub game_clock: u32,
 }
 
+/// Bumped whenever client and server messages change in an incompatible way,
+/// checked when connecting and again when joining.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Everything a client can send to the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// asks for a player on the board, answered with `HelloPlayer` or `JoinRejected`
+    Join {
+        name: String,
+        color: Rgb,
+        protocol_version: u32,
+    },
+    Input(InputMessage),
+    Ack(SnapshotAck),
+    /// answered with a `Pong` carrying the same timestamp, to measure latency
+    Ping { timestamp: f64 },
+    /// takes the player off the board, the connection stays open
+    Leave,
+    Chat(String),
+    /// watch the game through the camera without playing, a screen worth of it at a time
+    Spectate(Camera),
+}
+
+/// Why the server refused to let a client join.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinRejection {
+    NameTooShort,
+    NameTooLong,
+    /// only ascii letters, digits, spaces, `-` and `_` are allowed
+    InvalidCharacters,
+    NameTaken,
+    IncompatibleVersion { client: u32, server: u32 },
+}
+
+impl fmt::Display for JoinRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinRejection::NameTooShort => write!(
+                f,
+                "name must be at least {} characters long",
+                crate::config::MIN_NAME_LENGTH
+            ),
+            JoinRejection::NameTooLong => write!(
+                f,
+                "name can't be longer than {} characters",
+                crate::config::MAX_NAME_LENGTH
+            ),
+            JoinRejection::InvalidCharacters => {
+                write!(f, "name can only contain letters, digits, spaces, '-' and '_'")
+            }
+            JoinRejection::NameTaken => write!(f, "name is already taken"),
+            JoinRejection::IncompatibleVersion { client, server } => write!(
+                f,
+                "client speaks protocol version {}, server speaks {}",
+                client, server
+            ),
+        }
+    }
+}
+
+/// Everything the server can send to a client.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerMessage {
-    HelloPlayer(PlayerHandle, Game),
+    /// the player handle, the token needed to resume the session when it has a player,
+    /// and what the client can see of the game
+    HelloPlayer(PlayerHandle, Option<String>, Game),
+    JoinRejected(JoinRejection),
+    PlayerLeft(PlayerHandle),
+    State(Snapshot),
+    Pong { timestamp: f64 },
+    Chat {
+        from: PlayerHandle,
+        name: String,
+        text: String,
+    },
+    /// the last client message was refused
+    Error(ErrorReply),
+}
+
+/// Why the server refused a client message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ErrorReply {
+    /// the message could not be decoded, unknown messages included
+    Malformed(String),
+    IncompatibleVersion { client: u32, server: u32 },
+    /// the message needs a player on the board
+    NotJoined,
+    RateLimited,
+    ChatTooLong,
+    /// inputs for someone else's player, the connection is closed
+    Impersonation,
+    /// resuming a session without its token, the connection is closed
+    InvalidResumeToken,
+}
+
+impl fmt::Display for ErrorReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorReply::Malformed(reason) => write!(f, "malformed message: {}", reason),
+            ErrorReply::IncompatibleVersion { client, server } => write!(
+                f,
+                "client speaks protocol version {}, server speaks {}",
+                client, server
+            ),
+            ErrorReply::NotJoined => write!(f, "join the game first"),
+            ErrorReply::RateLimited => write!(f, "slow down"),
+            ErrorReply::ChatTooLong => write!(
+                f,
+                "chat messages can't be longer than {} characters",
+                crate::config::MAX_CHAT_LENGTH
+            ),
+            ErrorReply::Impersonation => write!(f, "you can only steer your own player"),
+            ErrorReply::InvalidResumeToken => write!(f, "this session can't be resumed"),
+        }
+    }
+}
+
+/// Wire format of a connection, picked by the client when connecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// human readable text frames, handy for debugging
+    Json,
+    /// compact binary frames, requires the `binary` feature
+    Binary,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+impl Encoding {
+    /// The most compact encoding this build can speak.
+    pub fn preferred() -> Self {
+        if cfg!(feature = "binary") {
+            Self::Binary
+        } else {
+            Self::Json
+        }
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::Json => true,
+            Self::Binary => cfg!(feature = "binary"),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Binary => "binary",
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Frame, ProtocolError> {
+        match self {
+            Self::Json => Ok(Frame::Text(serde_json::to_string(value)?)),
+            #[cfg(feature = "binary")]
+            Self::Binary => Ok(Frame::Binary(bincode::serialize(value)?)),
+            #[cfg(not(feature = "binary"))]
+            Self::Binary => Err(ProtocolError::UnsupportedEncoding(self)),
+        }
+    }
+}
+
+/// Query parameters of the websocket upgrade request.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConnectionParams {
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// protocol version of the client, refused right away when it doesn't match
+    #[serde(default)]
+    pub version: Option<u32>,
+    /// resume token from `HelloPlayer`, needed to take over a disconnected session
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl ConnectionParams {
+    pub fn check_version(&self) -> Result<(), ErrorReply> {
+        match self.version {
+            Some(version) if version != PROTOCOL_VERSION => Err(ErrorReply::IncompatibleVersion {
+                client: version,
+                server: PROTOCOL_VERSION,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the connection may resume the session that was handed `expected`.
+    pub fn check_token(&self, expected: Option<&String>) -> Result<(), ErrorReply> {
+        match (&self.token, expected) {
+            (Some(token), Some(expected)) if token == expected => Ok(()),
+            _ => Err(ErrorReply::InvalidResumeToken),
+        }
+    }
+}
+
+/// A single encoded websocket message.
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Frame {
+    /// Encoding the frame was sent in, servers answer in the one they accepted.
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            Frame::Text(_) => Encoding::Json,
+            Frame::Binary(_) => Encoding::Binary,
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, ProtocolError> {
+        match self {
+            Frame::Text(text) => Ok(serde_json::from_str(text)?),
+            #[cfg(feature = "binary")]
+            Frame::Binary(bytes) => Ok(bincode::deserialize(bytes)?),
+            #[cfg(not(feature = "binary"))]
+            Frame::Binary(_bytes) => Err(ProtocolError::UnsupportedEncoding(Encoding::Binary)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Json(serde_json::Error),
+    #[cfg(feature = "binary")]
+    Binary(bincode::Error),
+    UnsupportedEncoding(Encoding),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Json(e) => write!(f, "invalid json message: {}", e),
+            #[cfg(feature = "binary")]
+            ProtocolError::Binary(e) => write!(f, "invalid binary message: {}", e),
+            ProtocolError::UnsupportedEncoding(encoding) => {
+                write!(f, "unsupported encoding: {}", encoding.as_str())
+            }
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProtocolError {
+    fn from(e: serde_json::Error) -> Self {
+        ProtocolError::Json(e)
+    }
+}
+
+#[cfg(feature = "binary")]
+impl From<bincode::Error> for ProtocolError {
+    fn from(e: bincode::Error) -> Self {
+        ProtocolError::Binary(e)
+    }
+}
+
+#[cfg(test)]
+mod test_encoding {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut game = Game::new();
+        game.add(1);
+        let message = ServerMessage::HelloPlayer(1, Some("token".to_string()), game);
+        for &encoding in &[Encoding::Json, Encoding::Binary] {
+            if !encoding.is_supported() {
+                continue;
+            }
+            let frame = encoding.encode(&message).expect("encodes");
+            match frame.decode::<ServerMessage>().expect("decodes") {
+                ServerMessage::HelloPlayer(handle, token, game) => {
+                    assert_eq!(handle, 1);
+                    assert_eq!(token.as_deref(), Some("token"));
+                    assert!(game.players.contains_key(&1));
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_version_check() {
+        let mut params = ConnectionParams::default();
+        assert_eq!(params.check_version(), Ok(()));
+        params.version = Some(PROTOCOL_VERSION);
+        assert_eq!(params.check_version(), Ok(()));
+        params.version = Some(PROTOCOL_VERSION - 1);
+        assert_eq!(
+            params.check_version(),
+            Err(ErrorReply::IncompatibleVersion {
+                client: PROTOCOL_VERSION - 1,
+                server: PROTOCOL_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn test_resume_token() {
+        let expected = "0123abcd".to_string();
+        let mut params = ConnectionParams::default();
+        assert_eq!(params.check_token(Some(&expected)), Err(ErrorReply::InvalidResumeToken));
+        params.token = Some("guess".to_string());
+        assert_eq!(params.check_token(Some(&expected)), Err(ErrorReply::InvalidResumeToken));
+        assert_eq!(params.check_token(None), Err(ErrorReply::InvalidResumeToken));
+        params.token = Some(expected.clone());
+        assert_eq!(params.check_token(Some(&expected)), Ok(()));
+    }
+
+    #[test]
+    fn test_impersonation() {
+        use crate::game::UserInput;
+        assert!(!InputMessage::new(vec![(1, UserInput::Up)], 1, 1).impersonates(1));
+        assert!(InputMessage::new(vec![(1, UserInput::Up)], 1, 1).impersonates(2));
+        assert!(InputMessage::new(vec![(2, UserInput::Up)], 1, 1).impersonates(1));
+    }
+
+    #[test]
+    fn test_unknown_messages_are_errors() {
+        let frame = Frame::Text(r#"{"Teleport":{"x":1}}"#.to_string());
+        assert!(frame.decode::<ClientMessage>().is_err());
+        let frame = Frame::Text(r#"{"Ping":{"timestamp":12.5}}"#.to_string());
+        match frame.decode::<ClientMessage>().expect("decodes") {
+            ClientMessage::Ping { timestamp } => assert_eq!(timestamp, 12.5),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
 }
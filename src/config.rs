@@ -1,9 +1,225 @@
+use crate::boundary::BoundaryPolicy;
+use serde::{Deserialize, Serialize};
+
 pub static BACKEND_ADDRESS: &str = "127.0.0.1:3030";
+/// name the client settings are saved under
+pub static APP_NAME: &str = "prawario";
+
+// server related
+
+pub const TICK_RATE: u64 = 60;
+/// most ticks simulated back to back when the server falls behind, the rest is dropped
+pub const MAX_CATCH_UP_TICKS: u32 = 5;
+/// input messages a single client may send every tick, anything above is dropped,
+/// clients send at most one per tick whatever their frame rate
+pub const MAX_INPUTS_PER_TICK: u32 = 2;
+pub const MAX_INPUT_BURST: u32 = 20;
+pub const MAX_CHAT_PER_SECOND: u32 = 1;
+pub const MAX_CHAT_BURST: u32 = 5;
+pub const MAX_CHAT_LENGTH: usize = 200;
+pub const SNAPSHOT_RATE: u64 = 20;
+/// how many sent snapshots are kept around as delta baselines
+pub const SNAPSHOT_HISTORY: usize = 32;
 
+/// side of a single cell of the collision grid
+pub const SPATIAL_CELL_SIZE: f32 = 200.;
+/// seconds a disconnected player stays on the board waiting to reconnect, 0 removes them right away
+pub const DISCONNECT_GRACE_PERIOD: f32 = 10.;
+/// longest grace period the server accepts, ghosts of players who never come back crowd the board
+pub const MAX_DISCONNECT_GRACE_PERIOD: f32 = 300.;
 
-// game related
+// client related
+
+/// how many snapshots the client keeps to interpolate remote players between them
+pub const INTERPOLATION_BUFFER: usize = 8;
+/// seconds remote players keep moving on their own when snapshots are late
+pub const MAX_EXTRAPOLATION: f32 = 0.1;
+/// seconds between two attempts to reconnect after the socket closed
+pub const RECONNECT_INTERVAL: f32 = 1.;
+
+// game related, speeds are per second and durations in seconds,
+// `GameConfig` turns them into steps of whatever tick rate the server runs at
 
 pub const PLAYER_MIN_SIZE: f32 = 36.0;
-pub const PLAYER_DEFAULT_SPEED: f32 = 4.0;
+pub const PLAYER_DEFAULT_SPEED: f32 = 240.;
+/// how quickly players slow down as they grow, speed ~ (PLAYER_MIN_SIZE / size) ^ PLAYER_SPEED_FALLOFF
+pub const PLAYER_SPEED_FALLOFF: f32 = 0.44;
+pub const PLAYER_MIN_SPEED: f32 = 30.;
+/// part of the size of whatever a player eats that it grows by, see `GameConfig::growth_factor`
+pub const PLAYER_HEAL_FACTOR: f32 = 0.33;
+/// cells bigger than this slowly lose mass, but never shrink below it
+pub const MASS_DECAY_MIN_SIZE: f32 = 2. * PLAYER_MIN_SIZE;
+/// part of its size a big cell loses every second
+pub const MASS_DECAY_PER_SECOND: f32 = 0.01;
+/// smallest player able to split, each half is at least `PLAYER_MIN_SIZE`
+pub const PLAYER_MIN_SPLIT_SIZE: f32 = 2. * PLAYER_MIN_SIZE;
+/// most cells a single player can be split into
+pub const MAX_PLAYER_CELLS: usize = 16;
+/// initial speed of a freshly split cell
+pub const SPLIT_SPEED: f32 = 1440.;
+/// part of the split momentum kept after a second
+pub const SPLIT_MOMENTUM_DECAY: f32 = 0.0018;
+/// before split cells can merge back together
+pub const MERGE_COOLDOWN: f32 = 15.;
+/// between two splits of the same player, holding the key doesn't split every step
+pub const SPLIT_COOLDOWN: f32 = 1. / 6.;
+/// size a cell loses with every ejected piece of mass, which is as big
+pub const EJECT_SIZE: f32 = 6.;
+/// smallest cell able to eject mass
+pub const EJECT_MIN_SIZE: f32 = PLAYER_MIN_SIZE + EJECT_SIZE;
+/// initial speed of ejected mass
+pub const EJECT_SPEED: f32 = 1200.;
+pub const EJECT_COOLDOWN: f32 = 0.1;
+/// before ejected mass can be eaten by the player who ejected it
+pub const EJECT_OWNER_DELAY: f32 = 1.;
+/// part of their speed moving collectibles keep after a second
+pub const COLLECTIBLE_FRICTION: f32 = 0.0067;
+/// collectibles, hazards and split cells slower than this come to a stop
+pub const MIN_DRIFT_SPEED: f32 = 6.;
+/// hazards kept on the board, new ones appear until there are this many
+pub const HAZARD_COUNT: usize = 40;
+/// collectibles kept on every million square units of the board
+pub const COLLECTIBLE_DENSITY: f32 = 10.;
+/// most collectibles spawned on the board, however big it is
+pub const MAX_COLLECTIBLES: usize = 2000;
+/// between two refills of eaten collectibles
+pub const COLLECTIBLE_SPAWN_INTERVAL: f32 = 1. / 6.;
+/// most collectibles spawned by a single refill
+pub const COLLECTIBLE_SPAWN_BATCH: usize = 5;
+/// spawned collectibles are between these sizes, each one is worth its size
+pub const COLLECTIBLE_MIN_SIZE: f32 = 4.;
+pub const COLLECTIBLE_MAX_SIZE: f32 = 10.;
+/// between two new hazards, as long as there are less than `HAZARD_COUNT`
+pub const HAZARD_SPAWN_INTERVAL: f32 = 5. / 3.;
+/// cells up to this size pass under hazards, bigger ones pop
+pub const HAZARD_SIZE: f32 = 60.;
+/// most pieces a cell pops into when running into a hazard
+pub const HAZARD_POP_PIECES: usize = 8;
+/// part of its size a cell loses on a hazard when it can't pop any further
+pub const HAZARD_MASS_LOSS: f32 = 0.25;
+/// pieces of ejected mass a hazard swallows before shooting a new hazard
+pub const HAZARD_FEED_LIMIT: u32 = 7;
+/// initial speed of a hazard shot by a fed one
+pub const HAZARD_SHOT_SPEED: f32 = 1800.;
+pub const MIN_NAME_LENGTH: usize = 1;
+pub const MAX_NAME_LENGTH: usize = 16;
 pub const BOARD_WIDTH: f32 = 10000.;
 pub const BOARD_HEIGHT: f32 = 10000.;
+pub const BOUNDARY_POLICY: BoundaryPolicy = BoundaryPolicy::Clamp;
+/// size of the client canvas, the area a player of `PLAYER_MIN_SIZE` can see
+pub const VIEWPORT_WIDTH: f32 = 500.;
+pub const VIEWPORT_HEIGHT: f32 = 500.;
+/// extra distance around the viewport within which entities are still sent to the client
+pub const VIEWPORT_MARGIN: f32 = 100.;
+/// distance on screen between the player and the mouse at which players move at full speed
+pub const MOUSE_FULL_SPEED_DISTANCE: f32 = 150.;
+/// gamepad stick movement ignored by default, see `KeyBindings::dead_zone`
+pub const GAMEPAD_DEAD_ZONE: f32 = 0.15;
+
+/// Game rules the server can change without being rebuilt.
+///
+/// Chosen by the server and sent to every client along with the game, so that both sides
+/// simulate the same rules. Whatever isn't set keeps the value of the constant above.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub tick_rate: u64,
+    pub snapshot_rate: u64,
+    pub board_width: f32,
+    pub board_height: f32,
+    pub boundary: BoundaryPolicy,
+    pub collectible_density: f32,
+    pub max_collectibles: usize,
+    /// seconds between two refills
+    pub collectible_spawn_interval: f32,
+    pub collectible_spawn_batch: usize,
+    pub collectible_min_size: f32,
+    pub collectible_max_size: f32,
+    pub hazard_count: usize,
+    /// seconds between two new hazards
+    pub hazard_spawn_interval: f32,
+    /// part of the size of whatever a player eats that it grows by
+    pub growth_factor: f32,
+    pub mass_decay_per_second: f32,
+    pub mass_decay_min_size: f32,
+    /// seconds a disconnected player stays on the board waiting to reconnect
+    pub disconnect_grace_period: f32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            tick_rate: TICK_RATE,
+            snapshot_rate: SNAPSHOT_RATE,
+            board_width: BOARD_WIDTH,
+            board_height: BOARD_HEIGHT,
+            boundary: BOUNDARY_POLICY,
+            collectible_density: COLLECTIBLE_DENSITY,
+            max_collectibles: MAX_COLLECTIBLES,
+            collectible_spawn_interval: COLLECTIBLE_SPAWN_INTERVAL,
+            collectible_spawn_batch: COLLECTIBLE_SPAWN_BATCH,
+            collectible_min_size: COLLECTIBLE_MIN_SIZE,
+            collectible_max_size: COLLECTIBLE_MAX_SIZE,
+            hazard_count: HAZARD_COUNT,
+            hazard_spawn_interval: HAZARD_SPAWN_INTERVAL,
+            growth_factor: PLAYER_HEAL_FACTOR,
+            mass_decay_per_second: MASS_DECAY_PER_SECOND,
+            mass_decay_min_size: MASS_DECAY_MIN_SIZE,
+            disconnect_grace_period: DISCONNECT_GRACE_PERIOD,
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn ticks_per_snapshot(&self) -> u32 {
+        (self.tick_rate / self.snapshot_rate.max(1)).max(1) as u32
+    }
+
+    /// Seconds simulated by a single tick.
+    pub fn tick_seconds(&self) -> f32 {
+        1. / self.tick_rate as f32
+    }
+
+    /// Ticks lasting `seconds`, cooldowns and intervals are counted in them.
+    pub fn ticks(&self, seconds: f32) -> u32 {
+        (seconds * self.tick_rate as f32).round() as u32
+    }
+
+    /// Remote players are drawn this many ticks in the past, one snapshot interval.
+    pub fn interpolation_delay_ticks(&self) -> f32 {
+        self.ticks_per_snapshot() as f32
+    }
+
+    /// Refuses rules the game can't be played with, saying why.
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.tick_rate == 0 {
+            Err("tick_rate must be positive")
+        } else if self.snapshot_rate == 0 || self.snapshot_rate > self.tick_rate {
+            Err("snapshot_rate must be positive and at most tick_rate")
+        } else if !(self.board_width > 0. && self.board_height > 0.) {
+            Err("the board must be bigger than zero")
+        } else if self.ticks(self.collectible_spawn_interval) == 0
+            || self.ticks(self.hazard_spawn_interval) == 0
+        {
+            Err("spawn intervals must last at least a tick")
+        } else if !(self.collectible_density >= 0.) {
+            Err("collectible_density can't be negative")
+        } else if !(self.collectible_min_size > 0.
+            && self.collectible_min_size <= self.collectible_max_size)
+        {
+            Err("collectible sizes must be positive, the smallest one first")
+        } else if !(self.collectible_max_size < PLAYER_MIN_SIZE) {
+            Err("collectibles must be smaller than a new player, or nobody could eat them")
+        } else if !(self.growth_factor >= 0.) {
+            Err("growth_factor can't be negative")
+        } else if !(0. ..1.).contains(&self.mass_decay_per_second) {
+            Err("mass_decay_per_second must be at least 0 and below 1")
+        } else if !(self.mass_decay_min_size >= PLAYER_MIN_SIZE) {
+            Err("mass_decay_min_size can't be smaller than a new player")
+        } else if !(0. ..=MAX_DISCONNECT_GRACE_PERIOD).contains(&self.disconnect_grace_period) {
+            Err("disconnect_grace_period must be between 0 and 300 seconds")
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::game::UserInput;
+use quicksilver::geom::Vector;
+use quicksilver::input::{Event, GamepadAxis, GamepadButton, Key};
+use quicksilver::saving::{load, save, Location, SaveError};
+use quicksilver::Input;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Generates the conversions between `$kind` and the names it's saved under.
+macro_rules! named {
+    ($kind:ident, $to_name:ident, $from_name:ident, [$($variant:ident),* $(,)?]) => {
+        pub fn $to_name(value: $kind) -> Option<&'static str> {
+            match value {
+                $($kind::$variant => Some(stringify!($variant)),)*
+                #[allow(unreachable_patterns)]
+                _ => None,
+            }
+        }
+
+        pub fn $from_name(name: &str) -> Option<$kind> {
+            match name {
+                $(stringify!($variant) => Some($kind::$variant),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+named!(Key, key_name, key_from_name, [
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Up, Down, Left, Right, Space, Return, Tab, LShift, RShift, LControl, RControl,
+]);
+
+named!(GamepadButton, button_name, button_from_name, [
+    South, East, North, West, LeftShoulder, RightShoulder, LeftTrigger, RightTrigger,
+    Select, Start, LeftStick, RightStick, DPadUp, DPadDown, DPadLeft, DPadRight,
+]);
+
+/// Which keys and gamepad buttons trigger which input, any number of them can share an action.
+///
+/// Saved by name, so that the bindings survive the client being rebuilt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    keys: BTreeMap<String, UserInput>,
+    buttons: BTreeMap<String, UserInput>,
+    /// stick movement below this is ignored, worn out sticks never rest exactly at zero
+    pub dead_zone: f32,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut bindings = Self {
+            keys: BTreeMap::new(),
+            buttons: BTreeMap::new(),
+            dead_zone: crate::config::GAMEPAD_DEAD_ZONE,
+        };
+        for &(key, input) in &[
+            (Key::W, UserInput::Up),
+            (Key::S, UserInput::Down),
+            (Key::A, UserInput::Left),
+            (Key::D, UserInput::Right),
+            (Key::Up, UserInput::Up),
+            (Key::Down, UserInput::Down),
+            (Key::Left, UserInput::Left),
+            (Key::Right, UserInput::Right),
+            (Key::Space, UserInput::Split),
+            (Key::Q, UserInput::Eject),
+        ] {
+            bindings.bind_key(key, input);
+        }
+        for &(button, input) in &[
+            (GamepadButton::DPadUp, UserInput::Up),
+            (GamepadButton::DPadDown, UserInput::Down),
+            (GamepadButton::DPadLeft, UserInput::Left),
+            (GamepadButton::DPadRight, UserInput::Right),
+            (GamepadButton::South, UserInput::Split),
+            (GamepadButton::East, UserInput::Eject),
+        ] {
+            bindings.bind_button(button, input);
+        }
+        bindings
+    }
+}
+
+impl KeyBindings {
+    /// The bindings saved by the player, the defaults if there are none.
+    pub fn load() -> Self {
+        load(Location::Config, crate::config::APP_NAME, "key_bindings").unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), SaveError> {
+        save(Location::Config, crate::config::APP_NAME, "key_bindings", self)
+    }
+
+    /// Makes `key` trigger `input`, replacing whatever it triggered before.
+    pub fn bind_key(&mut self, key: Key, input: UserInput) {
+        if let Some(name) = key_name(key) {
+            self.keys.insert(name.to_string(), input);
+        }
+    }
+
+    pub fn unbind_key(&mut self, key: Key) {
+        if let Some(name) = key_name(key) {
+            self.keys.remove(name);
+        }
+    }
+
+    pub fn bind_button(&mut self, button: GamepadButton, input: UserInput) {
+        if let Some(name) = button_name(button) {
+            self.buttons.insert(name.to_string(), input);
+        }
+    }
+
+    pub fn unbind_button(&mut self, button: GamepadButton) {
+        if let Some(name) = button_name(button) {
+            self.buttons.remove(name);
+        }
+    }
+
+    pub fn input_for_key(&self, key: Key) -> Option<UserInput> {
+        self.keys.get(key_name(key)?).cloned()
+    }
+
+    pub fn keys_for(&self, input: UserInput) -> Vec<Key> {
+        self.keys
+            .iter()
+            .filter(|(_name, bound)| **bound == input)
+            .filter_map(|(name, _bound)| key_from_name(name))
+            .collect()
+    }
+
+    /// Everything held down right now, each input at most once.
+    pub fn pressed(&self, input: &Input, gamepad: &GamepadControls) -> Vec<UserInput> {
+        let keys = self
+            .keys
+            .iter()
+            .filter(|(name, _bound)| key_from_name(name).map_or(false, |key| input.key_down(key)))
+            .map(|(_name, bound)| *bound);
+        let buttons = self
+            .buttons
+            .iter()
+            .filter(|(name, _bound)| gamepad.is_down(name))
+            .map(|(_name, bound)| *bound);
+        let mut pressed: Vec<UserInput> = vec![];
+        for bound in keys.chain(buttons).chain(gamepad.steering(self.dead_zone)) {
+            if !pressed.contains(&bound) {
+                pressed.push(bound);
+            }
+        }
+        pressed
+    }
+}
+
+/// Starts rebinding the keys, every action then gets the next key pressed on top of its own.
+/// Never bound to an action, or there would be no way to start rebinding again.
+pub const REBIND_KEY: Key = Key::Tab;
+
+/// Actions rebound in turn, in this order.
+const REBINDABLE: [UserInput; 6] = [
+    UserInput::Up,
+    UserInput::Down,
+    UserInput::Left,
+    UserInput::Right,
+    UserInput::Split,
+    UserInput::Eject,
+];
+
+/// Walks the player through binding a key to every action, started by `REBIND_KEY`.
+#[derive(Debug, Clone, Default)]
+pub struct Rebinding {
+    next: Option<usize>,
+}
+
+impl Rebinding {
+    /// Returns true once every action got its new key, the bindings are worth saving then.
+    pub fn handle_event(&mut self, bindings: &mut KeyBindings, event: &Event) -> bool {
+        match event {
+            Event::KeyboardInput(event) if event.is_down() => match self.waiting_for() {
+                Some(_input) => self.bind(bindings, event.key()),
+                None => {
+                    if event.key() == REBIND_KEY {
+                        self.next = Some(0);
+                    }
+                    false
+                }
+            },
+            _ => false,
+        }
+    }
+
+    /// The action the next key pressed gets bound to.
+    pub fn waiting_for(&self) -> Option<UserInput> {
+        self.next.map(|next| REBINDABLE[next])
+    }
+
+    fn bind(&mut self, bindings: &mut KeyBindings, key: Key) -> bool {
+        let input = match (self.waiting_for(), key_name(key)) {
+            (Some(input), Some(_name)) if key != REBIND_KEY => input,
+            // keys that can't be saved are skipped, the action waits for another one
+            _ => return false,
+        };
+        // the other keys of the action keep working, only a key moved from another action leaves it
+        bindings.bind_key(key, input);
+        self.next = self.next.map(|next| next + 1).filter(|next| *next < REBINDABLE.len());
+        self.next.is_none()
+    }
+}
+
+/// Gamepad state gathered from quicksilver's gamepad events, all connected gamepads are merged.
+#[derive(Debug, Clone, Default)]
+pub struct GamepadControls {
+    buttons: BTreeSet<&'static str>,
+    stick: Vector,
+}
+
+impl GamepadControls {
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::GamepadButton(event) => {
+                if let Some(name) = button_name(event.button()) {
+                    self.set_button(name, event.is_down());
+                }
+            }
+            Event::GamepadAxis(event) => self.set_axis(event.axis(), event.value()),
+            _ => {}
+        }
+    }
+
+    fn set_button(&mut self, name: &'static str, down: bool) {
+        if down {
+            self.buttons.insert(name);
+        } else {
+            self.buttons.remove(name);
+        }
+    }
+
+    fn set_axis(&mut self, axis: GamepadAxis, value: f32) {
+        match axis {
+            GamepadAxis::LeftStickX => self.stick.x = value,
+            // sticks point up with positive values, the screen grows downwards
+            GamepadAxis::LeftStickY => self.stick.y = -value,
+            _ => {}
+        }
+    }
+
+    pub fn is_down(&self, name: &str) -> bool {
+        self.buttons.contains(name)
+    }
+
+    /// The left stick as an analog direction, `None` while it rests inside the dead zone.
+    pub fn steering(&self, dead_zone: f32) -> Option<UserInput> {
+        if self.stick.len() <= dead_zone {
+            return None;
+        }
+        UserInput::Steer(self.stick).sanitized()
+    }
+}
+
+#[cfg(test)]
+mod test_controls {
+    use super::*;
+
+    #[test]
+    fn test_many_keys_per_action() {
+        let mut bindings = KeyBindings::default();
+        assert_eq!(bindings.input_for_key(Key::Up), Some(UserInput::Up));
+        assert_eq!(bindings.input_for_key(Key::W), Some(UserInput::Up));
+        bindings.bind_key(Key::K, UserInput::Up);
+        bindings.unbind_key(Key::W);
+        assert_eq!(bindings.keys_for(UserInput::Up), vec![Key::K, Key::Up]);
+
+        let saved = serde_json::to_string(&bindings).unwrap();
+        let loaded: KeyBindings = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded, bindings);
+    }
+
+    #[test]
+    fn test_rebinding_every_action() {
+        let mut bindings = KeyBindings::default();
+        let mut rebinding = Rebinding::default();
+        assert!(!rebinding.bind(&mut bindings, Key::I));
+        assert_eq!(bindings.input_for_key(Key::I), None);
+
+        rebinding.next = Some(0);
+        assert!(!rebinding.bind(&mut bindings, REBIND_KEY));
+        assert_eq!(bindings.input_for_key(REBIND_KEY), None);
+        let keys = [Key::I, Key::K, Key::J, Key::L, Key::Return, Key::E];
+        for (n, key) in keys.iter().enumerate() {
+            assert_eq!(rebinding.waiting_for(), Some(REBINDABLE[n]));
+            assert_eq!(rebinding.bind(&mut bindings, *key), n == keys.len() - 1);
+        }
+        assert_eq!(rebinding.waiting_for(), None);
+        // the defaults are still there next to the new keys
+        assert_eq!(bindings.keys_for(UserInput::Up), vec![Key::I, Key::Up, Key::W]);
+        assert_eq!(bindings.keys_for(UserInput::Eject), vec![Key::E, Key::Q]);
+        assert_eq!(bindings.input_for_key(Key::Left), Some(UserInput::Left));
+    }
+
+    #[test]
+    fn test_gamepad_stick_up_steers_up_the_screen() {
+        let mut gamepad = GamepadControls::default();
+        gamepad.set_axis(GamepadAxis::LeftStickY, 1.);
+        assert_eq!(gamepad.steering(0.1), Some(UserInput::Steer(UserInput::Up.into())));
+        gamepad.set_axis(GamepadAxis::LeftStickY, -1.);
+        assert_eq!(gamepad.steering(0.1), Some(UserInput::Steer(UserInput::Down.into())));
+    }
+
+    #[test]
+    fn test_gamepad_stick_has_a_dead_zone() {
+        let mut gamepad = GamepadControls::default();
+        gamepad.set_axis(GamepadAxis::LeftStickX, 0.05);
+        assert_eq!(gamepad.steering(0.1), None);
+        gamepad.set_axis(GamepadAxis::LeftStickY, 0.5);
+        assert_eq!(gamepad.steering(0.1), Some(UserInput::Steer(Vector::new(0.05, -0.5))));
+
+        gamepad.set_button("South", true);
+        assert!(gamepad.is_down("South"));
+        gamepad.set_button("South", false);
+        assert!(!gamepad.is_down("South"));
+    }
+}
@@ -1,44 +1,88 @@
-use rand::rngs::ThreadRng;
-use rand::thread_rng;
-use crate::obstacles::CollectibleHandle;
-use crate::rendering::Render;
+use crate::obstacles::{CollectibleHandle, HazardHandle};
+use crate::rendering::{Render, Rgb};
 use itertools::Itertools;
-use quicksilver::input::Key;
 use quicksilver::Input;
 use quicksilver::{
-    geom::{Circle, Vector},
+    geom::{Circle, Transform, Vector},
     graphics::Color,
     Graphics,
 };
 
-use crate::communication::ClientMessage;
-use crate::obstacles::{Collectible, CollisionBetween, Obstacle};
+use crate::boundary::{BoundaryPolicy, Mobile};
+use crate::cell::{Cell, CellId};
+use crate::communication::{InputMessage, JoinRejection};
+use crate::config::GameConfig;
+use crate::controls::{GamepadControls, KeyBindings};
+use crate::interpolation::InterpolationBuffer;
+use crate::obstacles::{Collectible, CollisionBetween, Hazard, Obstacle};
+use crate::random::GameRng;
+use crate::snapshot::{Snapshot, SnapshotHistory};
+use crate::spatial::SpatialGrid;
+use crate::spawner;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
-use std::collections::HashMap;
-use std::convert::TryFrom;
+use serde_json::to_string;
+use std::collections::{HashMap, VecDeque};
 #[cfg(feature = "wee_alloc")]
 use wasm_bindgen::JsValue;
 #[cfg(feature = "wee_alloc")]
 use web_sys::console;
 
 pub type PlayerHandle = usize;
-use rand::{Rng, SeedableRng};
+use rand::Rng;
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
 pub enum UserInput {
     Left,
     Right,
     Up,
     Down,
+    /// divide every big enough cell in two
+    Split,
+    /// fire a bit of mass forward
+    Eject,
+    /// analog direction, towards the mouse, at most a unit long
+    Steer(#[serde(with = "VectorDef")] Vector),
+}
+
+impl UserInput {
+    /// Whether the input steers the player rather than triggering an action.
+    pub fn is_movement(self) -> bool {
+        match self {
+            Self::Split | Self::Eject => false,
+            Self::Left | Self::Right | Self::Up | Self::Down | Self::Steer(_) => true,
+        }
+    }
+
+    /// Rejects what no honest client sends and caps analog directions at full speed.
+    pub fn sanitized(self) -> Option<Self> {
+        match self {
+            Self::Steer(direction) => {
+                if !direction.x.is_finite() || !direction.y.is_finite() {
+                    return None;
+                }
+                if direction.len2() > 1. {
+                    return Some(Self::Steer(direction.normalize()));
+                }
+                Some(self)
+            }
+            input => Some(input),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub enum GameStateMutation {
-    KillPlayer(PlayerHandle),
-    HealPlayer(PlayerHandle, f32),
+    KillCell(CellId),
+    HealCell(CellId, f32),
     SpawnCollectible,
     DestroyCollectible(CollectibleHandle),
+    SpawnHazard,
+    /// the cell ran into the hazard, which is used up
+    PopCell(CellId, HazardHandle),
+    /// the hazard swallows a piece of ejected mass
+    FeedHazard(HazardHandle, CollectibleHandle),
+    /// big cells lose a bit of their size every step
+    DecayCell(CellId, f32),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -55,48 +99,6 @@ pub fn debug_log(string: String) {
     println!("{}", string);
 }
 
-static INPUTS: [UserInput; 4] = [
-    UserInput::Right,
-    UserInput::Left,
-    UserInput::Up,
-    UserInput::Down,
-];
-
-pub fn pressed_keys(input: &mut Input) -> Vec<UserInput> {
-    INPUTS
-        .iter()
-        .filter(|&&k| input.key_down(k.into()))
-        .map(|e: &UserInput| e.clone())
-        .collect()
-}
-
-impl TryFrom<Key> for UserInput {
-    type Error = ();
-
-    fn try_from(
-        key: Key,
-    ) -> std::result::Result<Self, <Self as std::convert::TryFrom<Key>>::Error> {
-        match key {
-            Key::W => Ok(Self::Up),
-            Key::S => Ok(Self::Down),
-            Key::A => Ok(Self::Left),
-            Key::D => Ok(Self::Right),
-            _ => Err(()),
-        }
-    }
-}
-
-impl Into<Key> for UserInput {
-    fn into(self) -> Key {
-        match self {
-            UserInput::Up => Key::W,
-            UserInput::Down => Key::S,
-            UserInput::Left => Key::A,
-            UserInput::Right => Key::D,
-        }
-    }
-}
-
 impl Into<Vector> for UserInput {
     fn into(self) -> Vector {
         match self {
@@ -104,6 +106,9 @@ impl Into<Vector> for UserInput {
             Self::Down => Vector { x: 0., y: 1. },
             Self::Left => Vector { x: -1., y: 0. },
             Self::Right => Vector { x: 1., y: 0. },
+            // actions don't steer
+            Self::Split | Self::Eject => Vector::ZERO,
+            Self::Steer(direction) => direction,
         }
     }
 }
@@ -116,10 +121,23 @@ pub struct Player {
     pub name: String,
     #[serde(with = "VectorDef")]
     pub position: Vector,
+    /// per second, the bigger the player the slower
     pub speed: f32,
     #[serde(with = "VectorDef")]
     pub direction: Vector,
     pub size: f32,
+    pub color: Rgb,
+    /// sequence number of the last client message the server applied to this player
+    pub input_sequence: u32,
+    /// game clock of the first step that ran with that message applied
+    pub input_clock: u32,
+    /// cells split off the main one, the fields above describe the main cell
+    pub fragments: Vec<Cell>,
+    /// game clock from which the player may split again
+    #[serde(skip)]
+    pub split_ready_at: u32,
+    #[serde(skip)]
+    pub eject_ready_at: u32,
 }
 
 impl Player {
@@ -133,7 +151,320 @@ impl Player {
     }
 
     pub fn heal(&mut self, amount: f32) {
-        self.size += amount*0.33;
+        self.resize(self.size + amount);
+    }
+
+    /// Changes the size, the speed follows it.
+    pub fn resize(&mut self, size: f32) {
+        self.size = size;
+        self.speed = Self::speed_for(size);
+    }
+
+    /// Agar-style movement, the bigger the player the slower it gets.
+    pub fn speed_for(size: f32) -> f32 {
+        let speed = crate::config::PLAYER_DEFAULT_SPEED
+            * (crate::config::PLAYER_MIN_SIZE / size).powf(crate::config::PLAYER_SPEED_FALLOFF);
+        speed.max(crate::config::PLAYER_MIN_SPEED)
+    }
+
+    /// Distance travelled in a second, diagonal movement is as fast as a straight one.
+    pub fn velocity(&self) -> Vector {
+        Self::velocity_along(self.direction, self.speed)
+    }
+
+    /// Directions up to a unit long are analog and move slower, longer ones go at full `speed`.
+    pub fn velocity_along(direction: Vector, speed: f32) -> Vector {
+        let length = direction.len();
+        if length == 0. {
+            return Vector::ZERO;
+        }
+        direction / length.max(1.) * speed
+    }
+
+    /// Moves by a single step worth of velocity, `dt` seconds long.
+    pub fn advance(&mut self, boundary: BoundaryPolicy, game_size: Vector, dt: f32) {
+        self.position += self.velocity() * dt;
+        boundary.apply(self, game_size);
+    }
+
+    /// Camera scale, bigger players see more of the board.
+    pub fn zoom(&self) -> f32 {
+        crate::config::PLAYER_MIN_SIZE / self.total_size()
+    }
+
+    pub fn main_cell(&self) -> Cell {
+        Cell {
+            position: self.position,
+            size: self.size,
+            ..Default::default()
+        }
+    }
+
+    /// The main cell followed by the fragments.
+    pub fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        std::iter::once(self.main_cell()).chain(self.fragments.iter().cloned())
+    }
+
+    pub fn cell(&self, index: usize) -> Option<Cell> {
+        match index {
+            0 => Some(self.main_cell()),
+            index => self.fragments.get(index - 1).cloned(),
+        }
+    }
+
+    pub fn total_size(&self) -> f32 {
+        self.size + self.fragments.iter().map(|fragment| fragment.size).sum::<f32>()
+    }
+
+    /// Center of the whole cell group, weighted by size, the camera follows it.
+    pub fn centroid(&self) -> Vector {
+        let weighted: Vector = self.cells().map(|cell| cell.position * cell.size).sum();
+        weighted / self.total_size()
+    }
+
+    /// Halves every cell big enough, the new halves shoot forward in the direction of movement.
+    pub fn split(&mut self, game_clock: u32, config: &GameConfig) {
+        if game_clock < self.split_ready_at || self.direction.len2() == 0. {
+            return;
+        }
+        self.split_ready_at = game_clock.wrapping_add(config.ticks(crate::config::SPLIT_COOLDOWN));
+        let heading = self.direction.normalize();
+        let merge_at = game_clock.wrapping_add(config.ticks(crate::config::MERGE_COOLDOWN));
+        let mut room = crate::config::MAX_PLAYER_CELLS.saturating_sub(self.fragments.len() + 1);
+        let mut halves = vec![];
+        let mut split_off = |position: Vector, size: f32| {
+            if room == 0 || size < crate::config::PLAYER_MIN_SPLIT_SIZE {
+                return None;
+            }
+            room -= 1;
+            let half = size / 2.;
+            halves.push(Cell {
+                // right next to the remaining half
+                position: position + heading * size,
+                size: half,
+                momentum: heading * crate::config::SPLIT_SPEED,
+                merge_at,
+            });
+            Some(half)
+        };
+        if let Some(half) = split_off(self.position, self.size) {
+            self.resize(half);
+        }
+        for fragment in self.fragments.iter_mut() {
+            if let Some(half) = split_off(fragment.position, fragment.size) {
+                fragment.size = half;
+            }
+            fragment.merge_at = merge_at;
+        }
+        self.fragments.extend(halves);
+    }
+
+    /// Sheds a bit of mass off every big enough cell, returns where the pieces
+    /// should appear, just outside of the cells in the direction of movement.
+    pub fn eject(&mut self, game_clock: u32, config: &GameConfig) -> Vec<Vector> {
+        if game_clock < self.eject_ready_at || self.direction.len2() == 0. {
+            return vec![];
+        }
+        self.eject_ready_at = game_clock.wrapping_add(config.ticks(crate::config::EJECT_COOLDOWN));
+        let heading = self.direction.normalize();
+        let shot_from = |position: Vector, size: f32| {
+            position + heading * (size + crate::config::EJECT_SIZE)
+        };
+        let mut shots = vec![];
+        if self.size >= crate::config::EJECT_MIN_SIZE {
+            self.resize(self.size - crate::config::EJECT_SIZE);
+            shots.push(shot_from(self.position, self.size));
+        }
+        for fragment in self.fragments.iter_mut() {
+            if fragment.size >= crate::config::EJECT_MIN_SIZE {
+                fragment.size -= crate::config::EJECT_SIZE;
+                shots.push(shot_from(fragment.position, fragment.size));
+            }
+        }
+        shots
+    }
+
+    /// Moves the fragments by a single step, the main cell is moved by `advance`.
+    pub fn advance_fragments(
+        &mut self,
+        boundary: BoundaryPolicy,
+        game_size: Vector,
+        game_clock: u32,
+        dt: f32,
+    ) {
+        let (direction, main) = (self.direction, self.position);
+        for fragment in self.fragments.iter_mut() {
+            fragment.advance(direction, main, game_clock, boundary, game_size, dt);
+        }
+    }
+
+    /// Folds the fragments whose cooldown is over back into the main cell once they touch it.
+    pub fn merge(&mut self, game_clock: u32, boundary: BoundaryPolicy, game_size: Vector) {
+        let main = self.main_cell();
+        let (merged, kept): (Vec<Cell>, Vec<Cell>) = self.fragments.drain(..).partition(|fragment| {
+            fragment.can_merge(game_clock) && main.collides(fragment, boundary, game_size)
+        });
+        self.fragments = kept;
+        for fragment in merged {
+            self.resize(self.size + fragment.size);
+        }
+    }
+
+    fn set_cell_size(&mut self, index: usize, size: f32) {
+        match index {
+            0 => self.resize(size),
+            index => {
+                if let Some(fragment) = self.fragments.get_mut(index - 1) {
+                    fragment.size = size;
+                }
+            }
+        }
+    }
+
+    /// The cell ran into a hazard and bursts into pieces flying apart,
+    /// or only loses mass when it's too small or there's no room for more cells.
+    pub fn pop(&mut self, index: usize, game_clock: u32, config: &GameConfig) {
+        let cell = match self.cell(index) {
+            Some(cell) if cell.size > 0. => cell,
+            _ => return,
+        };
+        let room = crate::config::MAX_PLAYER_CELLS.saturating_sub(self.fragments.len() + 1);
+        let pieces = (room + 1)
+            .min(crate::config::HAZARD_POP_PIECES)
+            .min((cell.size / crate::config::PLAYER_MIN_SIZE) as usize);
+        if pieces < 2 {
+            let size = cell.size * (1. - crate::config::HAZARD_MASS_LOSS);
+            self.set_cell_size(index, size.max(crate::config::PLAYER_MIN_SIZE));
+            return;
+        }
+        let size = cell.size / pieces as f32;
+        let merge_at = game_clock.wrapping_add(config.ticks(crate::config::MERGE_COOLDOWN));
+        self.set_cell_size(index, size);
+        if let Some(fragment) = index.checked_sub(1).and_then(|i| self.fragments.get_mut(i)) {
+            fragment.merge_at = merge_at;
+        }
+        for piece in 1..pieces {
+            let angle = std::f32::consts::PI * 2. * piece as f32 / pieces as f32;
+            let heading = Vector::new(angle.cos(), angle.sin());
+            self.fragments.push(Cell {
+                position: cell.position + heading * size * 2.,
+                size,
+                momentum: heading * crate::config::SPLIT_SPEED,
+                merge_at,
+            });
+        }
+    }
+
+    /// Shrinks a cell bigger than `min_size`, but never below it.
+    pub fn decay_cell(&mut self, index: usize, amount: f32, min_size: f32) {
+        let size = match self.cell(index) {
+            Some(cell) if cell.size > min_size => cell.size,
+            _ => return,
+        };
+        self.set_cell_size(index, (size - amount).max(min_size));
+    }
+
+    pub fn heal_cell(&mut self, index: usize, amount: f32) {
+        // the cell might have been eaten earlier in the same step
+        match index {
+            0 if self.size > 0. => self.heal(amount),
+            0 => {}
+            index => {
+                let fragment = self.fragments.get_mut(index - 1);
+                if let Some(fragment) = fragment.filter(|fragment| fragment.size > 0.) {
+                    fragment.heal(amount);
+                }
+            }
+        }
+    }
+
+    /// Marks a cell as eaten, it is only removed with `remove_dead_cells` so that
+    /// the indices of the other cells stay valid until the end of the step.
+    pub fn kill_cell(&mut self, index: usize) {
+        match index {
+            0 => self.size = 0.,
+            index => {
+                if let Some(fragment) = self.fragments.get_mut(index - 1) {
+                    fragment.size = 0.;
+                }
+            }
+        }
+    }
+
+    /// Drops the eaten cells, the biggest fragment takes over an eaten main cell.
+    ///
+    /// Returns `false` when nothing is left of the player.
+    pub fn remove_dead_cells(&mut self) -> bool {
+        self.fragments.retain(|fragment| fragment.size > 0.);
+        if self.size > 0. {
+            return true;
+        }
+        let biggest = self
+            .fragments
+            .iter()
+            .enumerate()
+            .fold(None, |biggest: Option<(usize, f32)>, (index, fragment)| match biggest {
+                Some((_index, size)) if size >= fragment.size => biggest,
+                _ => Some((index, fragment.size)),
+            });
+        match biggest {
+            Some((index, _size)) => {
+                let fragment = self.fragments.remove(index);
+                self.position = fragment.position;
+                self.resize(fragment.size);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// What a viewer's screen is centered on, spectators pick it themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Camera {
+    /// wherever the player goes, the middle of the board once they're gone
+    Follow(PlayerHandle),
+    /// a fixed spot on the board
+    Fixed(#[serde(with = "VectorDef")] Vector),
+}
+
+/// Part of the board visible on a player's screen.
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    pub center: Vector,
+    pub size: Vector,
+}
+
+impl Viewport {
+    pub fn of(player: &Player) -> Self {
+        let screen = Self::fixed(player.centroid());
+        Self {
+            size: screen.size / player.zoom(),
+            ..screen
+        }
+    }
+
+    /// A screen without any zoom, centered on `center`.
+    pub fn fixed(center: Vector) -> Self {
+        Self {
+            center,
+            size: Vector::new(
+                crate::config::VIEWPORT_WIDTH,
+                crate::config::VIEWPORT_HEIGHT,
+            ),
+        }
+    }
+
+    /// Whether the obstacle is on screen, on a wrapping board also when it's across the edge.
+    pub fn sees(&self, obstacle: &impl Obstacle, boundary: BoundaryPolicy, game_size: Vector) -> bool {
+        let reach = obstacle.radius() + crate::config::VIEWPORT_MARGIN;
+        let offset = boundary.offset(self.center, obstacle.center(), game_size);
+        offset.x.abs() <= self.size.x / 2. + reach && offset.y.abs() <= self.size.y / 2. + reach
+    }
+
+    /// Whether any cell of the player is visible.
+    pub fn sees_player(&self, player: &Player, boundary: BoundaryPolicy, game_size: Vector) -> bool {
+        player.cells().any(|cell| self.sees(&cell, boundary, game_size))
     }
 }
 
@@ -158,9 +489,30 @@ impl Obstacle for Player {
     }
 }
 
+impl Mobile for Player {
+    fn position_mut(&mut self) -> &mut Vector {
+        &mut self.position
+    }
+    fn direction_mut(&mut self) -> &mut Vector {
+        &mut self.direction
+    }
+}
+
+impl Player {
+    /// Draws the main cell at `position`, the fragments are shifted along with it.
+    pub fn render_at(&self, gfx: &mut Graphics, position: Vector) {
+        let color: Color = self.color.into();
+        let offset = position - self.position;
+        for fragment in self.fragments.iter() {
+            gfx.fill_circle(&Circle::new(fragment.position + offset, fragment.size), color);
+        }
+        gfx.fill_circle(&Circle::new(position, self.size as f32), color);
+    }
+}
+
 impl Render for Player {
     fn render(&self, gfx: &mut Graphics) {
-        gfx.fill_circle(&Circle::new(self.position, self.size as f32), Color::RED);
+        self.render_at(gfx, self.position);
     }
 }
 
@@ -169,26 +521,104 @@ pub struct Game {
     pub game_clock: u32,
     pub players: HashMap<PlayerHandle, Player>,
     pub collectibles: HashMap<CollectibleHandle, Collectible>,
+    pub hazards: HashMap<HazardHandle, Hazard>,
     pub active_player: Option<PlayerHandle>, // for frontend,
     #[serde(with = "VectorDef")]
     pub game_size: Vector,
+    pub boundary: BoundaryPolicy,
+    /// rules picked by the server, clients receive them with the game
+    pub config: GameConfig,
+    /// every random decision of the simulation is drawn from here, never sent to clients
+    pub rng: GameRng,
+    /// recent authoritative states received from the server, deltas are applied on top of
+    /// whichever one they were made against
+    #[serde(skip)]
+    pub baselines: SnapshotHistory,
+    /// client messages sent but not yet confirmed by the server, replayed on top of every snapshot
+    #[serde(skip)]
+    pub pending_inputs: VecDeque<InputMessage>,
+    #[serde(skip)]
+    pub input_sequence: u32,
+    /// server tick our own player has been predicted up to, ahead of `game_clock` by about a round trip
+    #[serde(skip)]
+    pub predicted_clock: u32,
+    /// recent snapshots, remote players are drawn in between them
+    #[serde(skip)]
+    pub interpolation: InterpolationBuffer,
+    /// seconds since the latest snapshot arrived, kept by the client
+    #[serde(skip)]
+    pub since_snapshot: f32,
+    /// collision lookup, refreshed at the start of every step
+    #[serde(skip)]
+    pub player_index: SpatialGrid,
+    /// cells in `player_index`, which stores positions in this list
+    #[serde(skip)]
+    pub indexed_cells: Vec<CellId>,
+    #[serde(skip)]
+    pub collectible_index: SpatialGrid,
+    #[serde(skip)]
+    pub hazard_index: SpatialGrid,
 }
 
 impl Render for Game {
     fn render(&self, gfx: &mut Graphics) {
         for collectible in self.collectibles.values() {
-            collectible.render(gfx);
+            collectible.render_at(gfx, self.on_screen(collectible.position));
         }
+        let render_clock = self.render_clock();
         for player in self.players.values() {
-            player.render(gfx);
+            // our own player is predicted, not interpolated
+            let position = if Some(player.handle) == self.active_player {
+                player.position
+            } else {
+                self.interpolation
+                    .position(player.handle, render_clock)
+                    .unwrap_or(player.position)
+            };
+            player.render_at(gfx, self.on_screen(position));
+        }
+        // small cells pass under hazards
+        for hazard in self.hazards.values() {
+            hazard.render_at(gfx, self.on_screen(hazard.position));
         }
     }
 }
 
 impl Game {
+    /// Where `position` is drawn, next to our player rather than on the far side of a wrapping board.
+    pub fn on_screen(&self, position: Vector) -> Vector {
+        match self.get_player() {
+            Some(player) => {
+                let center = player.centroid();
+                center + self.boundary.offset(center, position, self.game_size)
+            }
+            None => position,
+        }
+    }
+
+    /// Game clock remote players are drawn at, a snapshot interval in the past.
+    ///
+    /// Moves on at the tick rate from the latest snapshot, however often the screen refreshes.
+    pub fn render_clock(&self) -> f32 {
+        self.game_clock as f32 + self.since_snapshot * self.config.tick_rate as f32
+            - self.config.interpolation_delay_ticks()
+    }
+
     pub fn new() -> Self {
+        Self::with_seed(rand::random())
+    }
+
+    /// A game whose every random decision is reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_config(seed, GameConfig::default())
+    }
+
+    pub fn with_config(seed: u64, config: GameConfig) -> Self {
         let new_game = Self {
-            game_size: Vector::new(crate::config::BOARD_WIDTH, crate::config::BOARD_HEIGHT),
+            game_size: Vector::new(config.board_width, config.board_height),
+            boundary: config.boundary,
+            rng: GameRng::new(seed),
+            config,
             ..Default::default()
         };
 
@@ -206,69 +636,280 @@ impl Game {
         key
     }
 
-    pub fn add_collectible(&mut self) -> CollectibleHandle {
+    /// Checks the name a player wants to join with, returns it without surrounding whitespace.
+    pub fn validate_name(&self, name: &str) -> Result<String, JoinRejection> {
+        let name = name.trim();
+        let length = name.chars().count();
+        if length < crate::config::MIN_NAME_LENGTH {
+            return Err(JoinRejection::NameTooShort);
+        }
+        if length > crate::config::MAX_NAME_LENGTH {
+            return Err(JoinRejection::NameTooLong);
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_')
+        {
+            return Err(JoinRejection::InvalidCharacters);
+        }
+        if self
+            .players
+            .values()
+            .any(|player| player.name.eq_ignore_ascii_case(name))
+        {
+            return Err(JoinRejection::NameTaken);
+        }
+        Ok(name.to_string())
+    }
+
+    /// Puts a named player on the board.
+    pub fn join(
+        &mut self,
+        key: PlayerHandle,
+        name: &str,
+        color: Rgb,
+    ) -> Result<PlayerHandle, JoinRejection> {
+        let name = self.validate_name(name)?;
+        let handle = self.add(key);
+        let player = self
+            .players
+            .get_mut(&handle)
+            .expect("player was just added");
+        player.name = name;
+        player.color = color;
+        Ok(handle)
+    }
+
+    /// Spawns a collectible somewhere away from the players, `None` if no free spot turned up.
+    pub fn add_collectible(&mut self) -> Option<CollectibleHandle> {
         if cfg!(feature = "client") {
-            return 0
+            return None
         }
+        let cells: Vec<Cell> = self.players.values().flat_map(|player| player.cells()).collect();
+        let position = spawner::free_spot(&mut self.rng, self.boundary, self.game_size, &cells)?;
+        let (size, color) = spawner::random_looks(&mut self.rng, &self.config);
         let next_id = self.random_id();
-        let position = self.get_random_location();
         self.collectibles.insert(
             next_id,
             Collectible {
                 direction: Vector::ZERO,
                 handle: next_id,
                 position,
-                size: 5.,
+                size,
+                color,
                 speed: 0.,
                 ..Default::default()
             }
         );
-        next_id
+        Some(next_id)
+    }
+
+    /// Fires the mass ejected by the player as moving collectibles.
+    pub fn eject(&mut self, owner: PlayerHandle) {
+        let game_clock = self.game_clock;
+        let (shots, heading, color) = match self.players.get_mut(&owner) {
+            Some(player) => (player.eject(game_clock, &self.config), player.direction, player.color),
+            None => return,
+        };
+        for position in shots {
+            let handle = self.random_id();
+            self.collectibles.insert(
+                handle,
+                Collectible {
+                    handle,
+                    position,
+                    direction: heading.normalize(),
+                    speed: crate::config::EJECT_SPEED,
+                    size: crate::config::EJECT_SIZE,
+                    color,
+                    ejected_by: Some(owner),
+                    edible_at: game_clock
+                        .wrapping_add(self.config.ticks(crate::config::EJECT_OWNER_DELAY)),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    pub fn add_hazard(&mut self) -> HazardHandle {
+        if cfg!(feature = "client") {
+            return 0
+        }
+        let handle = self.random_id();
+        let position = self.get_random_location();
+        self.hazards.insert(
+            handle,
+            Hazard {
+                handle,
+                position,
+                size: crate::config::HAZARD_SIZE,
+                ..Default::default()
+            },
+        );
+        handle
+    }
+
+    /// Fires a new hazard out of a fed one, in the direction the mass was flying.
+    fn shoot_hazard(&mut self, from: HazardHandle, direction: Vector) {
+        let position = match self.hazards.get(&from) {
+            Some(hazard) if direction.len2() > 0. => hazard.position,
+            _ => return,
+        };
+        let heading = direction.normalize();
+        let handle = self.random_id();
+        self.hazards.insert(
+            handle,
+            Hazard {
+                handle,
+                position: position + heading * crate::config::HAZARD_SIZE * 2.,
+                direction: heading,
+                speed: crate::config::HAZARD_SHOT_SPEED,
+                size: crate::config::HAZARD_SIZE,
+                fed: 0,
+            },
+        );
     }
 
     pub fn remove(&mut self, key: &PlayerHandle) {
         self.players.remove(&key);
     }
 
-    pub fn get_player_input(&mut self, mut input: &mut Input) -> Vec<PlayerInput> {
+    pub fn get_player_input(
+        &mut self,
+        input: &Input,
+        bindings: &KeyBindings,
+        gamepad: &GamepadControls,
+    ) -> Vec<PlayerInput> {
         if let Some(player_handle) = self.active_player {
-            return pressed_keys(&mut input)
+            return bindings
+                .pressed(input, gamepad)
                 .into_iter()
-                .map(|key| (player_handle, key.into()))
+                .map(|pressed| (player_handle, pressed))
                 .collect();
         }
         vec![]
     }
 
-    pub fn to_client_message(&self, inputs: &Vec<PlayerInput>) -> Option<ClientMessage> {
+    /// Analog steering towards the mouse at `mouse` on screen, seen through the `camera` transform.
+    ///
+    /// Full speed once the mouse is `MOUSE_FULL_SPEED_DISTANCE` away from the player on screen.
+    pub fn steering_input(&self, camera: Transform, mouse: Vector) -> Option<PlayerInput> {
+        let player = self.get_player()?;
+        let target = camera.inverse() * mouse;
+        let direction = (target - player.centroid()) * player.zoom()
+            / crate::config::MOUSE_FULL_SPEED_DISTANCE;
+        Some((player.handle, UserInput::Steer(direction).sanitized()?))
+    }
+
+    /// Wraps the inputs of this frame into a numbered message, remembering it until the server confirms it.
+    pub fn to_input_message(&mut self, inputs: &Vec<PlayerInput>) -> Option<InputMessage> {
         if inputs.is_empty() {
             return None;
         }
-        Some(ClientMessage {
+        let message = InputMessage {
             inputs: inputs.clone(),
             player_handle: self.active_player?,
-        })
+            sequence: self.input_sequence.wrapping_add(1),
+            game_clock: self.predicted_clock,
+        };
+        self.input_sequence = message.sequence;
+        self.pending_inputs.push_back(message.clone());
+        Some(message)
     }
 
-    pub fn should_spawn_collectible(&self) -> bool {
-        self.game_clock % 100 == 1
+    /// Whether this step is one of every `interval` steps, shifted by `offset`.
+    fn every(&self, interval: u32, offset: u32) -> bool {
+        self.game_clock % interval == offset % interval
+    }
+
+    pub fn should_spawn_hazard(&self) -> bool {
+        self.hazards.len() < self.config.hazard_count
+            && self.every(self.config.ticks(self.config.hazard_spawn_interval), 51)
     }
 
     pub fn handle_inputs(&mut self, inputs: Vec<PlayerInput>) {
-        for (handle, directions) in &inputs.iter().group_by(|(handle, _direction)| handle) {
+        for (handle, directions) in &inputs
+            .iter()
+            .filter(|(_handle, input)| input.is_movement())
+            .group_by(|(handle, _direction)| handle)
+        {
             if let Some(mut player) = self.players.get_mut(handle) {
                 player.direction = directions.map(|player_input| player_input.1.into()).sum();
             }
         }
     }
 
-    pub fn handle_client_message(&mut self, message: &ClientMessage) {
-        self.handle_inputs(message.inputs.clone());
+    /// Applies a message received from the connection owning `owner`.
+    ///
+    /// Only ever steers the owner's player, whatever handles the message claims.
+    pub fn handle_input_message(&mut self, owner: PlayerHandle, message: &InputMessage) {
+        let inputs = message
+            .inputs
+            .iter()
+            .filter(|(handle, _input)| *handle == owner)
+            .filter_map(|&(handle, input)| Some((handle, input.sanitized()?)))
+            .collect::<Vec<PlayerInput>>();
+        let actions: Vec<UserInput> = inputs
+            .iter()
+            .map(|(_handle, input)| *input)
+            .filter(|input| !input.is_movement())
+            .collect();
+        self.handle_inputs(inputs);
+        let game_clock = self.game_clock;
+        if let Some(player) = self.players.get_mut(&owner) {
+            player.input_sequence = message.sequence;
+            player.input_clock = game_clock;
+        }
+        // after steering, so that the actions fire where the player is heading now
+        for action in actions {
+            self.apply_action(owner, action);
+        }
     }
 
-    pub fn handle_quicksilver_input(&mut self, mut input: &mut Input) {
+    fn apply_action(&mut self, owner: PlayerHandle, action: UserInput) {
+        let game_clock = self.game_clock;
+        match action {
+            UserInput::Split => {
+                if let Some(player) = self.players.get_mut(&owner) {
+                    player.split(game_clock, &self.config);
+                }
+            }
+            UserInput::Eject => self.eject(owner),
+            UserInput::Left
+            | UserInput::Right
+            | UserInput::Up
+            | UserInput::Down
+            | UserInput::Steer(_) => {}
+        }
+    }
+
+    /// Moves our own player `ticks` server ticks ahead, without waiting for the server.
+    ///
+    /// Everyone else only moves with the snapshots, the client never runs a full `step`.
+    pub fn predict(&mut self, ticks: u32) {
+        for _tick in 0..ticks {
+            let (boundary, game_size, game_clock) =
+                (self.boundary, self.game_size, self.predicted_clock);
+            let dt = self.config.tick_seconds();
+            if let Some(player) = self
+                .active_player
+                .and_then(|handle| self.players.get_mut(&handle))
+            {
+                player.advance(boundary, game_size, dt);
+                player.advance_fragments(boundary, game_size, game_clock, dt);
+            }
+            self.predicted_clock = self.predicted_clock.wrapping_add(1);
+        }
+    }
+
+    pub fn handle_quicksilver_input(
+        &mut self,
+        input: &Input,
+        bindings: &KeyBindings,
+        gamepad: &GamepadControls,
+    ) {
         if let Some(_player_handle) = self.active_player {
-            let inputs = self.get_player_input(&mut input);
+            let inputs = self.get_player_input(input, bindings, gamepad);
             // debug_log(format!("inputs: {:?}", inputs));
             self.handle_inputs(inputs);
         }
@@ -279,25 +920,31 @@ impl Game {
     }
 
     pub fn random_id(&self) -> usize {
-        let mut all: Vec<&usize> = self.collectibles.keys().chain(self.players.keys()).collect();
-        all.sort();
-        *all.last().or(Some(&&1usize)).unwrap().clone() + 1
+        self.collectibles
+            .keys()
+            .chain(self.players.keys())
+            .chain(self.hazards.keys())
+            .max()
+            .unwrap_or(&1)
+            + 1
     }
 
     pub fn step(&mut self) {
         debug_assert!(self.game_size.x > 0.);
         debug_assert!(self.game_size.y > 0.);
 
+        self.update_index();
         for mutation in self.mutations() {
             match mutation {
-                GameStateMutation::KillPlayer(player_handle) => {
-                    self.players.remove_entry(&player_handle);
+                GameStateMutation::KillCell(cell) => {
+                    if let Some(player) = self.players.get_mut(&cell.player) {
+                        player.kill_cell(cell.index);
+                    }
                 }
-                GameStateMutation::HealPlayer(player_handle, amount) => {
-                    self.players
-                        .get_mut(&player_handle)
-                        .expect(format!("player #{} not found", player_handle,).as_str())
-                        .heal(amount);
+                GameStateMutation::HealCell(cell, amount) => {
+                    if let Some(player) = self.players.get_mut(&cell.player) {
+                        player.heal_cell(cell.index, amount * self.config.growth_factor);
+                    }
                 }
                 GameStateMutation::SpawnCollectible => {
                     self.add_collectible();
@@ -305,38 +952,196 @@ impl Game {
                 GameStateMutation::DestroyCollectible(collectible_handle) => {
                     self.collectibles.remove_entry(&collectible_handle);
                 }
+                GameStateMutation::SpawnHazard => {
+                    self.add_hazard();
+                }
+                GameStateMutation::PopCell(cell, hazard_handle) => {
+                    // a hazard pops a single cell, even when several ran into it at once
+                    if self.hazards.remove(&hazard_handle).is_none() {
+                        continue;
+                    }
+                    let game_clock = self.game_clock;
+                    if let Some(player) = self.players.get_mut(&cell.player) {
+                        player.pop(cell.index, game_clock, &self.config);
+                    }
+                }
+                GameStateMutation::DecayCell(cell, amount) => {
+                    if let Some(player) = self.players.get_mut(&cell.player) {
+                        player.decay_cell(cell.index, amount, self.config.mass_decay_min_size);
+                    }
+                }
+                GameStateMutation::FeedHazard(hazard_handle, collectible_handle) => {
+                    if !self.hazards.contains_key(&hazard_handle) {
+                        continue;
+                    }
+                    let fed = match self.collectibles.remove(&collectible_handle) {
+                        Some(collectible) => collectible,
+                        None => continue,
+                    };
+                    let shoot = self
+                        .hazards
+                        .get_mut(&hazard_handle)
+                        .map_or(false, |hazard| hazard.feed());
+                    if shoot {
+                        self.shoot_hazard(hazard_handle, fed.direction);
+                    }
+                }
             }
         }
 
+        self.players.retain(|_handle, player| player.remove_dead_cells());
+
+        let (boundary, game_size, game_clock) = (self.boundary, self.game_size, self.game_clock);
+        let dt = self.config.tick_seconds();
         for player in self.players.values_mut() {
-            let direction = player.direction.clone();
-            player.position += direction;
+            player.advance(boundary, game_size, dt);
+            player.advance_fragments(boundary, game_size, game_clock, dt);
+            player.merge(game_clock, boundary, game_size);
+        }
+        for collectible in self.collectibles.values_mut() {
+            collectible.advance(boundary, game_size, dt);
+        }
+        for hazard in self.hazards.values_mut() {
+            hazard.advance(boundary, game_size, dt);
         }
 
         self.clock_tick();
     }
 
+    /// The part of the game visible to `viewer` through their own player.
+    pub fn view_for(&self, viewer: PlayerHandle) -> Self {
+        self.view_from(Camera::Follow(viewer))
+    }
+
+    /// What the camera points at, never more than a screen worth of the board.
+    pub fn viewport(&self, camera: Camera) -> Viewport {
+        match camera {
+            Camera::Follow(handle) => match self.players.get(&handle) {
+                Some(player) => Viewport::of(player),
+                None => Viewport::fixed(self.game_size / 2.),
+            },
+            Camera::Fixed(center) => Viewport::fixed(center),
+        }
+    }
+
+    /// The part of the game visible through `camera`.
+    pub fn view_from(&self, camera: Camera) -> Self {
+        let viewport = self.viewport(camera);
+        Self {
+            game_clock: self.game_clock,
+            players: self
+                .players
+                .iter()
+                .filter(|(_handle, player)| {
+                    viewport.sees_player(player, self.boundary, self.game_size)
+                })
+                .map(|(&handle, player)| (handle, player.clone()))
+                .collect(),
+            collectibles: self
+                .collectibles
+                .iter()
+                .filter(|(_handle, collectible)| {
+                    viewport.sees(*collectible, self.boundary, self.game_size)
+                })
+                .map(|(&handle, collectible)| (handle, collectible.clone()))
+                .collect(),
+            hazards: self
+                .hazards
+                .iter()
+                .filter(|(_handle, hazard)| viewport.sees(*hazard, self.boundary, self.game_size))
+                .map(|(&handle, hazard)| (handle, hazard.clone()))
+                .collect(),
+            active_player: self.active_player,
+            game_size: self.game_size,
+            boundary: self.boundary,
+            config: self.config.clone(),
+            // clients get a fresh generator, the server's one would tell them every spawn to come
+            ..Default::default()
+        }
+    }
+
     pub fn state_dump(&self) -> String {
         to_string(self).expect(format!("was unable to dump {:#?}", self).as_str())
     }
 
-    pub fn update_state(&mut self, new_state: String) {
-        if let Ok(state) = from_str(new_state.as_str()) {
-            let active_player = self.active_player.clone();
-            let game_size = self.game_size.clone();
-            *self = Self {
-                active_player,
-                game_size,
-                ..state
+    /// Replaces the local state with the one received from the server.
+    ///
+    /// Returns the game clock of the applied snapshot so that it can be acknowledged,
+    /// or `None` if it was a delta against a baseline we don't have.
+    pub fn update_state(&mut self, snapshot: Snapshot) -> Option<u32> {
+        let state = match snapshot {
+            Snapshot::Full(state) => state,
+            Snapshot::Delta(delta) => delta.apply(self.baselines.get(delta.baseline_clock)?)?,
+        };
+        let game_clock = state.game_clock;
+        let active_player = self.active_player.clone();
+        let game_size = self.game_size.clone();
+        let pending_inputs = std::mem::take(&mut self.pending_inputs);
+        let mut baselines = std::mem::take(&mut self.baselines);
+        baselines.push(state.clone());
+        let mut interpolation = std::mem::take(&mut self.interpolation);
+        interpolation.push(&state);
+        *self = Self {
+            active_player,
+            game_size,
+            baselines,
+            pending_inputs,
+            input_sequence: self.input_sequence,
+            predicted_clock: self.predicted_clock,
+            interpolation,
+            since_snapshot: 0.,
+            ..state
+        };
+        self.reconcile();
+        Some(game_clock)
+    }
+
+    /// Drops the inputs the server already applied and replays the rest on top of the authoritative state,
+    /// a step per server tick from the snapshot up to where the prediction got.
+    fn reconcile(&mut self) {
+        let (acknowledged, applied_at) = match self.get_player() {
+            Some(player) => (player.input_sequence, player.input_clock),
+            None => {
+                self.pending_inputs.clear();
+                return;
+            }
+        };
+        // the server applied the message later than we thought, a round trip ahead is where inputs land
+        let tagged = self
+            .pending_inputs
+            .iter()
+            .find(|message| message.sequence == acknowledged)
+            .map(|message| message.game_clock);
+        if let Some(tagged) = tagged {
+            let late = applied_at.wrapping_sub(tagged);
+            self.predicted_clock = self.predicted_clock.wrapping_add(late);
+            for message in self.pending_inputs.iter_mut() {
+                message.game_clock = message.game_clock.wrapping_add(late);
+            }
+        }
+        self.pending_inputs
+            .retain(|message| message.sequence > acknowledged);
+
+        let until = self.predicted_clock.max(self.game_clock);
+        self.predicted_clock = self.game_clock;
+        let mut pending = self.pending_inputs.clone().into_iter().peekable();
+        while self.predicted_clock < until {
+            let game_clock = self.predicted_clock;
+            while let Some(message) = pending.next_if(|message| message.game_clock <= game_clock) {
+                self.handle_inputs(message.inputs);
             }
+            self.predict(1);
+        }
+        // sent this very tick, the direction is already in effect
+        for message in pending {
+            self.handle_inputs(message.inputs);
         }
     }
 
-    pub fn get_random_location(&self) -> Vector {
-        let mut rng = thread_rng();
+    pub fn get_random_location(&mut self) -> Vector {
         let pos = Vector::new(
-            rng.gen_range(0.0, self.game_size.x),
-            rng.gen_range(0.0, self.game_size.y),
+            self.rng.gen_range(0.0, self.game_size.x),
+            self.rng.gen_range(0.0, self.game_size.y),
         );
         pos
     }
@@ -345,57 +1150,144 @@ impl Game {
         self.active_player.is_some()
     }
 
+    /// Rebuilds the spatial index from the current positions, collisions are looked up in it.
+    pub fn update_index(&mut self) {
+        let (boundary, game_size) = (self.boundary, self.game_size);
+        self.player_index.set_boundary(boundary, game_size);
+        self.collectible_index.set_boundary(boundary, game_size);
+        self.hazard_index.set_boundary(boundary, game_size);
+        self.player_index.clear();
+        self.indexed_cells.clear();
+        // in handle order, so that the positions in `indexed_cells` are reproducible
+        for player in self.players.values().sorted_by_key(|player| player.handle) {
+            for (index, cell) in player.cells().enumerate() {
+                self.player_index.insert(self.indexed_cells.len(), &cell);
+                self.indexed_cells.push(CellId {
+                    player: player.handle,
+                    index,
+                });
+            }
+        }
+        self.collectible_index.clear();
+        for (&handle, collectible) in self.collectibles.iter() {
+            self.collectible_index.insert(handle, collectible);
+        }
+        self.hazard_index.clear();
+        for (&handle, hazard) in self.hazards.iter() {
+            self.hazard_index.insert(handle, hazard);
+        }
+    }
+
+    pub fn cell(&self, id: CellId) -> Option<Cell> {
+        self.players.get(&id.player)?.cell(id.index)
+    }
+
+    /// Indexed cells in the order of `indexed_cells`, so that mutations are produced in a reproducible order.
+    fn sorted_cells(&self) -> impl Iterator<Item = (usize, CellId, Cell)> + '_ {
+        self.indexed_cells
+            .iter()
+            .enumerate()
+            .filter_map(move |(position, &id)| Some((position, id, self.cell(id)?)))
+    }
+
     pub fn player_collisions(&self) -> Vec<CollisionBetween> {
-        self.players
-            .values()
-            .combinations(2)
-            .map(|pair| (pair[0], pair[1]))
-            .filter(|(one, other)| one.collides(*other))
-            .map(|(one, other)| CollisionBetween::PlayerAndPlayer(one.handle, other.handle))
-            .collect()
+        let mut collisions = vec![];
+        for (position, one_id, one) in self.sorted_cells() {
+            for other_position in self.player_index.query(one.center(), one.radius()) {
+                // every pair is checked once, from the side of the lower position
+                if other_position <= position {
+                    continue;
+                }
+                let other_id = self.indexed_cells[other_position];
+                // cells of the same player don't eat each other, they merge
+                if other_id.player == one_id.player {
+                    continue;
+                }
+                if let Some(other) = self.cell(other_id) {
+                    if one.collides(&other, self.boundary, self.game_size) {
+                        collisions.push(CollisionBetween::PlayerAndPlayer(one_id, other_id));
+                    }
+                }
+            }
+        }
+        collisions
     }
 
     pub fn collectible_collisions(&self) -> Vec<CollisionBetween> {
         let mut collisions = vec![];
-        for player in self.players.values() {
-            for collectible in self.collectibles.values() {
-                if player.collides(collectible) {
-                    collisions.push(CollisionBetween::PlayerAndCollectible(
-                        player.handle,
-                        collectible.handle,
-                    ))
+        for (_position, cell_id, cell) in self.sorted_cells() {
+            for collectible_handle in self.collectible_index.query(cell.center(), cell.radius()) {
+                if let Some(collectible) = self.collectibles.get(&collectible_handle) {
+                    if cell.collides(collectible, self.boundary, self.game_size) {
+                        collisions.push(CollisionBetween::PlayerAndCollectible(
+                            cell_id,
+                            collectible.handle,
+                        ))
+                    }
                 }
             }
         }
         collisions
     }
+
+    pub fn hazard_collisions(&self) -> Vec<CollisionBetween> {
+        let mut collisions = vec![];
+        for (_position, cell_id, cell) in self.sorted_cells() {
+            for hazard_handle in self.hazard_index.query(cell.center(), cell.radius()) {
+                if let Some(hazard) = self.hazards.get(&hazard_handle) {
+                    if cell.collides(hazard, self.boundary, self.game_size) {
+                        collisions.push(CollisionBetween::PlayerAndHazard(cell_id, hazard_handle));
+                    }
+                }
+            }
+        }
+        for hazard in self.hazards.values().sorted_by_key(|hazard| hazard.handle) {
+            for collectible_handle in self.collectible_index.query(hazard.center(), hazard.radius()) {
+                if let Some(collectible) = self.collectibles.get(&collectible_handle) {
+                    // only ejected mass feeds hazards, the rest lies around for players
+                    if collectible.ejected_by.is_some()
+                        && hazard.collides(collectible, self.boundary, self.game_size)
+                    {
+                        collisions.push(CollisionBetween::CollectibleAndHazard(
+                            collectible_handle,
+                            hazard.handle,
+                        ));
+                    }
+                }
+            }
+        }
+        collisions
+    }
+
     pub fn to_mutation(&self, collision: &CollisionBetween) -> Option<Vec<GameStateMutation>> {
         match collision {
-            CollisionBetween::PlayerAndPlayer(one, other) => {
-                let (one, other) = (self.players.get(&one)?, self.players.get(&other)?);
-                if one.can_kill(other) {
+            CollisionBetween::PlayerAndPlayer(one_id, other_id) => {
+                let (one, other) = (self.cell(*one_id)?, self.cell(*other_id)?);
+                if one.can_kill(&other) {
                     return Some(vec![
-                        GameStateMutation::KillPlayer(other.handle),
-                        GameStateMutation::HealPlayer(one.handle, other.size),
+                        GameStateMutation::KillCell(*other_id),
+                        GameStateMutation::HealCell(*one_id, other.size),
                     ]);
-                } else if other.can_kill(one) {
+                } else if other.can_kill(&one) {
                     return Some(vec![
-                        GameStateMutation::KillPlayer(one.handle),
-                        GameStateMutation::HealPlayer(other.handle, one.size),
+                        GameStateMutation::KillCell(*one_id),
+                        GameStateMutation::HealCell(*other_id, one.size),
                     ]);
                 } else {
                     return None;
                 }
             }
-            CollisionBetween::PlayerAndCollectible(player_handle, collectible_handle) => {
-                let (player, collectible) = (
-                    self.players.get(player_handle)?,
+            CollisionBetween::PlayerAndCollectible(cell_id, collectible_handle) => {
+                let (cell, collectible) = (
+                    self.cell(*cell_id)?,
                     self.collectibles.get(collectible_handle)?,
                 );
-                if player.can_kill(collectible) {
+                if cell.can_kill(collectible)
+                    && collectible.edible_by(cell_id.player, self.game_clock)
+                {
                     return Some(vec![
-                        GameStateMutation::HealPlayer(
-                            player.handle,
+                        GameStateMutation::HealCell(
+                            *cell_id,
                             collectible.strength(),
                         ),
                         GameStateMutation::DestroyCollectible(*collectible_handle),
@@ -404,21 +1296,47 @@ impl Game {
                     return None;
                 }
             }
+            CollisionBetween::PlayerAndHazard(cell_id, hazard_handle) => {
+                let (cell, hazard) = (self.cell(*cell_id)?, self.hazards.get(hazard_handle)?);
+                // small cells pass under
+                if cell.can_kill(hazard) {
+                    return Some(vec![GameStateMutation::PopCell(*cell_id, *hazard_handle)]);
+                } else {
+                    return None;
+                }
+            }
+            CollisionBetween::CollectibleAndHazard(collectible_handle, hazard_handle) => Some(vec![
+                GameStateMutation::FeedHazard(*hazard_handle, *collectible_handle),
+            ]),
         }
     }
 
+    /// Keeps the leaders from growing forever, the bigger the cell the more it loses.
+    pub fn decay(&self) -> Vec<GameStateMutation> {
+        let rate = self.config.mass_decay_per_second / self.config.tick_rate as f32;
+        self.sorted_cells()
+            .filter(|(_position, _cell_id, cell)| cell.size > self.config.mass_decay_min_size)
+            .map(|(_position, cell_id, cell)| GameStateMutation::DecayCell(cell_id, cell.size * rate))
+            .collect()
+    }
+
     pub fn mutations(&self) -> Vec<GameStateMutation> {
-        let collectibles = {
-            if self.should_spawn_collectible() {
-                vec![GameStateMutation::SpawnCollectible]
-            } else { vec![] }
+        let collectibles = (0..spawner::due(&self.config, self.game_clock, self.collectibles.len()))
+            .map(|_spawn| GameStateMutation::SpawnCollectible);
+        let hazards = if self.should_spawn_hazard() {
+            vec![GameStateMutation::SpawnHazard]
+        } else {
+            vec![]
         };
         self.player_collisions()
             .iter()
             .chain(self.collectible_collisions().iter())
+            .chain(self.hazard_collisions().iter())
             .filter_map(|c| self.to_mutation(c))
             .flatten()
-            .chain(collectibles.into_iter())
+            .chain(self.decay().into_iter())
+            .chain(collectibles)
+            .chain(hazards.into_iter())
             .collect()
     }
 }
@@ -447,6 +1365,84 @@ mod test_movement {
         );
     }
 
+    #[test]
+    fn test_diagonal_movement_is_not_faster() {
+        let mut game = Game::new();
+        game.add(1);
+        game.add(2);
+        game.players.get_mut(&2).unwrap().position = Vector::new(1000., 1000.);
+        game.handle_inputs(vec![(1, UserInput::Right)]);
+        game.handle_inputs(vec![(2, UserInput::Right), (2, UserInput::Up)]);
+        game.step();
+        let straight = game.players[&1].position;
+        let diagonal = game.players[&2].position - Vector::new(1000., 1000.);
+        let step = crate::config::PLAYER_DEFAULT_SPEED * game.config.tick_seconds();
+        assert!((straight.len() - step).abs() < 0.001);
+        assert!((diagonal.len() - straight.len()).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_bigger_players_are_slower() {
+        let mut player = Player::new(1);
+        assert_eq!(player.speed, crate::config::PLAYER_DEFAULT_SPEED);
+        player.heal(100.);
+        assert!(player.speed < crate::config::PLAYER_DEFAULT_SPEED);
+        player.resize(1_000_000.);
+        assert_eq!(player.speed, crate::config::PLAYER_MIN_SPEED);
+
+        player.direction = Vector::new(0., 3.);
+        assert_eq!(player.velocity(), Vector::new(0., player.speed));
+        player.direction = Vector::ZERO;
+        assert_eq!(player.velocity(), Vector::ZERO);
+    }
+
+    #[test]
+    fn test_mouse_steering_is_analog() {
+        let mut game = Game::with_seed(1);
+        game.add(1);
+        game.active_player = Some(1);
+        let camera = Transform::translate(Vector::new(250., 250.));
+        let full_speed = crate::config::MOUSE_FULL_SPEED_DISTANCE;
+
+        let half_way = Vector::new(250. + full_speed / 2., 250.);
+        let steering = game.steering_input(camera, half_way).unwrap();
+        assert_eq!(steering, (1, UserInput::Steer(Vector::new(0.5, 0.))));
+        game.handle_input_message(1, &InputMessage::new(vec![steering], 1, 1));
+        game.step();
+        let step = crate::config::PLAYER_DEFAULT_SPEED * game.config.tick_seconds();
+        assert_eq!(game.players[&1].position, Vector::new(step / 2., 0.));
+
+        // far away is as fast as a key press
+        let far = game.steering_input(camera, Vector::new(250., 250. - full_speed * 10.));
+        assert_eq!(far.unwrap().1, UserInput::Steer(Vector::new(0., -1.)));
+    }
+
+    #[test]
+    fn test_steering_is_validated() {
+        let nan = UserInput::Steer(Vector::new(std::f32::NAN, 0.));
+        assert_eq!(nan.sanitized(), None);
+        let cheating = UserInput::Steer(Vector::new(0., 100.));
+        assert_eq!(cheating.sanitized(), Some(UserInput::Steer(Vector::new(0., 1.))));
+
+        let mut game = Game::with_seed(1);
+        game.add(1);
+        game.handle_input_message(1, &InputMessage::new(vec![(1, cheating)], 1, 1));
+        game.step();
+        let step = crate::config::PLAYER_DEFAULT_SPEED * game.config.tick_seconds();
+        assert_eq!(game.players[&1].position, Vector::new(0., step));
+    }
+
+    #[test]
+    fn test_players_stay_on_the_board() {
+        let mut game = Game::new();
+        game.add(1);
+        game.handle_inputs(vec![(1, UserInput::Left), (1, UserInput::Up)]);
+        for _i in 0..10 {
+            game.step();
+        }
+        assert_eq!(game.players[&1].position, Vector::ZERO);
+    }
+
     #[test]
     fn test_random_positions() {
         let mut game = Game::new();
@@ -456,6 +1452,81 @@ mod test_movement {
         assert!(pos.x <= crate::config::BOARD_WIDTH);
     }
 
+    #[test]
+    fn test_game_follows_its_config() {
+        let config = GameConfig {
+            board_width: 300.,
+            board_height: 200.,
+            hazard_count: 0,
+            collectible_density: 1000.,
+            ..GameConfig::default()
+        };
+        let mut game = Game::with_config(1, config.clone());
+        assert_eq!(game.game_size, Vector::new(300., 200.));
+        // the board is stocked by the first step and stays that way
+        game.step();
+        assert_eq!(game.collectibles.len(), 60);
+        for _i in 0..200 {
+            game.step();
+        }
+        assert!(game.hazards.is_empty());
+        assert_eq!(game.collectibles.len(), 60);
+        // clients play by the rules they receive with the game
+        game.add(1);
+        assert_eq!(game.view_for(1).config, config);
+    }
+
+    #[test]
+    fn test_tick_rate_keeps_wall_clock_behaviour() {
+        // a second of moving right, then a split
+        let play = |tick_rate: u64| {
+            let config = GameConfig {
+                tick_rate,
+                snapshot_rate: 10,
+                hazard_count: 0,
+                collectible_density: 0.,
+                ..GameConfig::default()
+            };
+            let mut game = Game::with_config(1, config);
+            game.add(1);
+            game.handle_inputs(vec![(1, UserInput::Right)]);
+            for _tick in 0..tick_rate {
+                game.step();
+            }
+            let moved = game.players[&1].position;
+            game.players.get_mut(&1).unwrap().resize(crate::config::PLAYER_MIN_SIZE * 4.);
+            game.handle_input_message(1, &InputMessage::new(vec![(1, UserInput::Split)], 1, 1));
+            let merge_in = game.players[&1].fragments[0].merge_at - game.game_clock;
+            (moved, merge_in as f32 / tick_rate as f32)
+        };
+        let (fast, slow) = (play(60), play(30));
+        assert!((fast.0.x - crate::config::PLAYER_DEFAULT_SPEED).abs() < 0.01);
+        assert!((fast.0 - slow.0).len() < 0.01);
+        assert_eq!(fast.1, crate::config::MERGE_COOLDOWN);
+        assert_eq!(slow.1, crate::config::MERGE_COOLDOWN);
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_world() {
+        let play = |seed| {
+            let mut game = Game::with_seed(seed);
+            game.add(1);
+            for _i in 0..500 {
+                game.handle_inputs(vec![(1, UserInput::Right), (1, UserInput::Down)]);
+                game.step();
+            }
+            let collectibles: Vec<Vector> = game
+                .collectibles
+                .values()
+                .sorted_by_key(|collectible| collectible.handle)
+                .map(|collectible| collectible.position)
+                .collect();
+            (game.players[&1].position, collectibles)
+        };
+        assert_eq!(play(7), play(7));
+        assert_ne!(play(7).1, play(8).1);
+    }
+
     #[test]
     fn test_game_does_not_crash() {
         let mut game = Game::new();
@@ -466,3 +1537,591 @@ mod test_movement {
         // assert!(game.collectibles.len() > 10);
     }
 }
+
+#[cfg(test)]
+mod test_visibility {
+    use super::*;
+
+    #[test]
+    fn test_view_only_contains_nearby_entities() {
+        let mut game = Game::new();
+        game.add(1);
+        game.add(2);
+        game.add(3);
+        game.players.get_mut(&2).unwrap().position = Vector::new(200., 0.);
+        game.players.get_mut(&3).unwrap().position = Vector::new(5000., 5000.);
+
+        let view = game.view_for(1);
+        assert!(view.players.contains_key(&1));
+        assert!(view.players.contains_key(&2));
+        assert!(!view.players.contains_key(&3));
+
+        // growing widens the view
+        game.players.get_mut(&1).unwrap().size = crate::config::PLAYER_MIN_SIZE * 20.;
+        assert!(game.view_for(1).players.contains_key(&3));
+
+        // spectators see no more than a screen either
+        let view = game.view_from(Camera::Fixed(Vector::new(5000., 5000.)));
+        assert_eq!(view.players.keys().collect::<Vec<_>>(), vec![&3]);
+        let view = game.view_from(Camera::Follow(2));
+        assert!(view.players.contains_key(&2));
+        assert!(!view.players.contains_key(&3));
+    }
+
+    #[test]
+    fn test_views_keep_the_random_state_secret() {
+        let mut game = Game::with_seed(7);
+        game.add(1);
+        game.step();
+        let mut view = game.view_for(1);
+        assert_ne!(view.rng.gen::<u64>(), game.rng.clone().gen::<u64>());
+    }
+
+    #[test]
+    fn test_view_without_a_player_is_bounded() {
+        let mut game = Game::new();
+        game.add(1);
+        game.add(2);
+        game.players.get_mut(&1).unwrap().position = Vector::new(0., 0.);
+        game.players.get_mut(&2).unwrap().position = game.game_size / 2.;
+        // nobody to follow, the camera stays in the middle of the board
+        let view = game.view_for(3);
+        assert!(!view.players.contains_key(&1));
+        assert!(view.players.contains_key(&2));
+    }
+
+    #[test]
+    fn test_view_across_a_wrapping_edge() {
+        let mut game = Game::with_seed(1);
+        game.add(1);
+        game.add(2);
+        game.players.get_mut(&1).unwrap().position = Vector::new(10., 500.);
+        let across = Vector::new(game.game_size.x - 10., 500.);
+        game.players.get_mut(&2).unwrap().position = across;
+        assert!(!game.view_for(1).players.contains_key(&2));
+
+        game.boundary = BoundaryPolicy::Wrap;
+        assert!(game.view_for(1).players.contains_key(&2));
+        // and drawn right next to our player, not on the far side of the board
+        game.active_player = Some(1);
+        assert_eq!(game.on_screen(across), Vector::new(-10., 500.));
+    }
+}
+
+#[cfg(test)]
+mod test_collisions {
+    use super::*;
+
+    #[test]
+    fn test_index_finds_the_same_collisions_as_brute_force() {
+        let mut game = Game::with_seed(1);
+        for handle in 1..=50 {
+            game.add(handle);
+            let position = game.get_random_location() / 20.;
+            let player = game.players.get_mut(&handle).unwrap();
+            player.position = position;
+            player.size = crate::config::PLAYER_MIN_SIZE + handle as f32;
+        }
+        // spawned collectibles keep clear of the players, these are put right among them
+        for handle in 1000..1500 {
+            let position = game.get_random_location() / 20.;
+            game.collectibles.insert(
+                handle,
+                Collectible {
+                    handle,
+                    position,
+                    size: crate::config::COLLECTIBLE_MIN_SIZE,
+                    color: Rgb::BLUE,
+                    ..Default::default()
+                },
+            );
+        }
+        game.update_index();
+
+        let brute_force_players = game
+            .players
+            .values()
+            .combinations(2)
+            .filter(|pair| pair[0].collides(pair[1], game.boundary, game.game_size))
+            .count();
+        let brute_force_collectibles = game
+            .players
+            .values()
+            .cartesian_product(game.collectibles.values())
+            .filter(|(player, collectible)| {
+                player.collides(*collectible, game.boundary, game.game_size)
+            })
+            .count();
+        assert!(brute_force_players > 0);
+        assert!(brute_force_collectibles > 0);
+        assert_eq!(game.player_collisions().len(), brute_force_players);
+        assert_eq!(game.collectible_collisions().len(), brute_force_collectibles);
+    }
+
+    #[test]
+    fn test_cells_touch_across_a_wrapping_edge() {
+        let size = crate::config::PLAYER_MIN_SIZE;
+        let config = GameConfig {
+            boundary: BoundaryPolicy::Wrap,
+            ..GameConfig::default()
+        };
+        let mut game = Game::with_config(1, config);
+        let edge = game.game_size.x;
+        game.add(1);
+        game.add(2);
+        let hunter = game.players.get_mut(&1).unwrap();
+        hunter.position = Vector::new(10., 500.);
+        hunter.resize(size * 2.);
+        hunter.fragments.push(Cell {
+            position: Vector::new(edge - 20., 520.),
+            size,
+            ..Default::default()
+        });
+        game.players.get_mut(&2).unwrap().position = Vector::new(edge - 10., 480.);
+        game.update_index();
+        assert_eq!(game.player_collisions().len(), 1);
+
+        game.step();
+        assert!(!game.players.contains_key(&2));
+        // the fragment merged back the short way, across the edge
+        assert!(game.players[&1].fragments.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test_split {
+    use super::*;
+    use crate::config::{MERGE_COOLDOWN, PLAYER_MIN_SIZE, SPLIT_COOLDOWN};
+
+    fn split_player(size: f32) -> Game {
+        // nothing to eat on the way, the sizes only change by splitting and merging
+        let config = GameConfig {
+            collectible_density: 0.,
+            ..GameConfig::default()
+        };
+        let mut game = Game::with_config(1, config);
+        game.add(1);
+        let player = game.players.get_mut(&1).unwrap();
+        player.position = Vector::new(1000., 1000.);
+        player.resize(size);
+        let inputs = vec![(1, UserInput::Right), (1, UserInput::Split)];
+        game.handle_input_message(1, &InputMessage::new(inputs, 1, 1));
+        game
+    }
+
+    #[test]
+    fn test_split_halves_the_player() {
+        let game = split_player(PLAYER_MIN_SIZE * 4.);
+        let player = &game.players[&1];
+        assert_eq!(player.size, PLAYER_MIN_SIZE * 2.);
+        assert_eq!(player.fragments.len(), 1);
+        assert_eq!(player.total_size(), PLAYER_MIN_SIZE * 4.);
+        assert!(player.fragments[0].position.x > player.position.x);
+        assert!(player.fragments[0].momentum.x > 0.);
+        assert_eq!(player.direction, Vector::new(1., 0.));
+
+        // too small to split
+        assert!(split_player(PLAYER_MIN_SIZE).players[&1].fragments.is_empty());
+    }
+
+    #[test]
+    fn test_cells_merge_after_the_cooldown() {
+        let mut game = split_player(PLAYER_MIN_SIZE * 4.);
+        for _i in 0..game.config.ticks(SPLIT_COOLDOWN) {
+            game.step();
+        }
+        // splitting again restarts the cooldown of every cell
+        game.handle_input_message(1, &InputMessage::new(vec![(1, UserInput::Split)], 1, 2));
+        assert_eq!(game.players[&1].fragments.len(), 3);
+        let flown = game.players[&1].fragments[0].position.x - game.players[&1].position.x;
+        assert!(flown > PLAYER_MIN_SIZE * 2.);
+
+        for _i in 0..game.config.ticks(MERGE_COOLDOWN) {
+            game.step();
+            assert!(!game.players[&1].fragments.is_empty());
+        }
+        for _i in 0..200 {
+            game.step();
+        }
+        let player = &game.players[&1];
+        assert!(player.fragments.is_empty());
+        // merged back, a bit of it has decayed since
+        assert!(player.size <= PLAYER_MIN_SIZE * 4.);
+        assert!(player.size > PLAYER_MIN_SIZE * 3.9);
+    }
+
+    #[test]
+    fn test_fragment_takes_over_eaten_main_cell() {
+        let mut game = split_player(PLAYER_MIN_SIZE * 4.);
+        let fragment = game.players[&1].fragments[0].clone();
+        let position = game.players[&1].position;
+        game.add(2);
+        let hunter = game.players.get_mut(&2).unwrap();
+        hunter.position = position;
+        hunter.resize(PLAYER_MIN_SIZE * 3.);
+        game.step();
+
+        let player = &game.players[&1];
+        assert!(player.fragments.is_empty());
+        assert_eq!(player.size, fragment.size);
+        assert!(player.position.x > 1000. + PLAYER_MIN_SIZE);
+    }
+
+    #[test]
+    fn test_camera_follows_the_centroid() {
+        let mut player = Player::new(1);
+        player.fragments.push(Cell {
+            position: Vector::new(300., 0.),
+            size: PLAYER_MIN_SIZE * 2.,
+            ..Default::default()
+        });
+        assert_eq!(player.centroid(), Vector::new(200., 0.));
+        assert_eq!(Viewport::of(&player).center, Vector::new(200., 0.));
+        assert_eq!(player.zoom(), 1. / 3.);
+    }
+}
+
+#[cfg(test)]
+mod test_eject {
+    use super::*;
+    use crate::config::{EJECT_OWNER_DELAY, EJECT_SIZE, PLAYER_MIN_SIZE};
+
+    fn ejecting_game() -> Game {
+        let mut game = Game::with_seed(1);
+        game.add(1);
+        let player = game.players.get_mut(&1).unwrap();
+        player.position = Vector::new(1000., 1000.);
+        player.resize(PLAYER_MIN_SIZE * 2.);
+        let inputs = vec![(1, UserInput::Right), (1, UserInput::Eject)];
+        game.handle_input_message(1, &InputMessage::new(inputs, 1, 1));
+        game
+    }
+
+    #[test]
+    fn test_ejected_mass_flies_and_stops() {
+        let mut game = ejecting_game();
+        // standing still, so that the player doesn't run into its own mass
+        game.players.get_mut(&1).unwrap().direction = Vector::ZERO;
+        assert_eq!(game.players[&1].size, PLAYER_MIN_SIZE * 2. - EJECT_SIZE);
+        let (&handle, ejected) = game.collectibles.iter().next().expect("mass was ejected");
+        assert_eq!(ejected.ejected_by, Some(1));
+        assert!(ejected.position.x > game.players[&1].position.x + game.players[&1].size);
+
+        let start = ejected.position;
+        for _i in 0..200 {
+            game.step();
+        }
+        let ejected = &game.collectibles[&handle];
+        assert_eq!(ejected.speed, 0.);
+        let first_step = crate::config::EJECT_SPEED * game.config.tick_seconds();
+        assert!(ejected.position.x > start.x + first_step);
+        assert_eq!(ejected.position.y, start.y);
+    }
+
+    #[test]
+    fn test_owner_eats_its_mass_only_after_a_delay() {
+        let mut game = ejecting_game();
+        let ejected = game.collectibles.values().next().unwrap().clone();
+        let owner = game.cell(CellId { player: 1, index: 0 }).unwrap();
+        let too_early = game.game_clock + game.config.ticks(EJECT_OWNER_DELAY) - 1;
+        assert!(!ejected.edible_by(1, too_early));
+        assert!(ejected.edible_by(2, too_early));
+        assert!(ejected.edible_by(1, too_early + 1));
+
+        // right on top of its owner, still not eaten
+        game.collectibles.get_mut(&ejected.handle).unwrap().position = owner.position;
+        game.collectibles.get_mut(&ejected.handle).unwrap().speed = 0.;
+        game.step();
+        assert!(game.collectibles.contains_key(&ejected.handle));
+        game.game_clock = too_early + 1;
+        game.step();
+        assert!(!game.collectibles.contains_key(&ejected.handle));
+    }
+}
+
+#[cfg(test)]
+mod test_hazards {
+    use super::*;
+    use crate::config::{HAZARD_FEED_LIMIT, HAZARD_MASS_LOSS, HAZARD_SIZE, PLAYER_MIN_SIZE};
+
+    fn hazard_game(player_size: f32) -> Game {
+        let mut game = Game::with_seed(1);
+        game.hazards.insert(
+            100,
+            Hazard {
+                handle: 100,
+                position: Vector::new(1000., 1000.),
+                size: HAZARD_SIZE,
+                ..Default::default()
+            },
+        );
+        game.add(1);
+        let player = game.players.get_mut(&1).unwrap();
+        player.position = Vector::new(1000., 1000.);
+        player.resize(player_size);
+        game
+    }
+
+    #[test]
+    fn test_small_players_pass_under() {
+        let mut game = hazard_game(PLAYER_MIN_SIZE);
+        game.step();
+        assert!(game.hazards.contains_key(&100));
+        assert!(game.players[&1].fragments.is_empty());
+        assert_eq!(game.players[&1].size, PLAYER_MIN_SIZE);
+    }
+
+    #[test]
+    fn test_big_players_pop() {
+        let size = PLAYER_MIN_SIZE * 5.;
+        let mut game = hazard_game(size);
+        game.step();
+        assert!(!game.hazards.contains_key(&100));
+        let player = &game.players[&1];
+        assert_eq!(player.fragments.len(), 4);
+        assert!((player.total_size() - size).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_players_without_room_lose_mass() {
+        let size = PLAYER_MIN_SIZE * 5.;
+        let mut game = hazard_game(size);
+        let player = game.players.get_mut(&1).unwrap();
+        for i in 1..crate::config::MAX_PLAYER_CELLS {
+            player.fragments.push(Cell {
+                position: Vector::new(5000., 1000. + 100. * i as f32),
+                size: PLAYER_MIN_SIZE,
+                ..Default::default()
+            });
+        }
+        game.step();
+        assert!(!game.hazards.contains_key(&100));
+        let player = &game.players[&1];
+        assert_eq!(player.fragments.len(), crate::config::MAX_PLAYER_CELLS - 1);
+        // and a tiny bit of decay
+        assert!((player.size - size * (1. - HAZARD_MASS_LOSS)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_fed_hazard_shoots_a_new_one() {
+        let mut game = hazard_game(PLAYER_MIN_SIZE);
+        game.remove(&1);
+        for i in 0..HAZARD_FEED_LIMIT {
+            assert_eq!(game.hazards.len(), 1);
+            game.collectibles.insert(
+                200 + i as usize,
+                Collectible {
+                    handle: 200 + i as usize,
+                    position: Vector::new(1000., 1000.),
+                    direction: Vector::new(0., 1.),
+                    size: crate::config::EJECT_SIZE,
+                    color: Rgb::BLUE,
+                    ejected_by: Some(1),
+                    ..Default::default()
+                },
+            );
+            game.step();
+        }
+        assert!(game.collectibles.values().all(|collectible| collectible.ejected_by.is_none()));
+        assert_eq!(game.hazards[&100].fed, 0);
+        let (_handle, shot) = game
+            .hazards
+            .iter()
+            .find(|(&handle, _hazard)| handle != 100)
+            .expect("a new hazard was shot");
+        assert_eq!(shot.direction, Vector::new(0., 1.));
+        assert!(shot.position.y > 1000. + HAZARD_SIZE * 2.);
+    }
+}
+
+#[cfg(test)]
+mod test_decay {
+    use super::*;
+    use crate::config::{MASS_DECAY_MIN_SIZE, MASS_DECAY_PER_SECOND, TICK_RATE};
+
+    #[test]
+    fn test_big_players_shrink_down_to_the_floor() {
+        let mut game = Game::with_seed(1);
+        game.add(1);
+        game.add(2);
+        game.players.get_mut(&1).unwrap().resize(MASS_DECAY_MIN_SIZE * 2.);
+        game.players.get_mut(&2).unwrap().position = Vector::new(5000., 5000.);
+        game.step();
+        let shrunk = game.players[&1].size;
+        let expected = MASS_DECAY_MIN_SIZE * 2. * (1. - MASS_DECAY_PER_SECOND / TICK_RATE as f32);
+        assert!((shrunk - expected).abs() < 0.001);
+        // small players don't decay
+        assert_eq!(game.players[&2].size, crate::config::PLAYER_MIN_SIZE);
+
+        game.players.get_mut(&1).unwrap().resize(MASS_DECAY_MIN_SIZE + 0.0001);
+        game.step();
+        assert_eq!(game.players[&1].size, MASS_DECAY_MIN_SIZE);
+    }
+}
+
+#[cfg(test)]
+mod bench_step {
+    extern crate test;
+    use super::*;
+    use test::Bencher;
+
+    /// A single step has to stay well below 16ms to keep up with 60 ticks per second.
+    #[bench]
+    fn bench_crowded_step(b: &mut Bencher) {
+        // a small board, so that cells keep running into each other and into collectibles
+        let config = GameConfig {
+            board_width: 3000.,
+            board_height: 3000.,
+            collectible_density: 550.,
+            max_collectibles: 5000,
+            collectible_spawn_batch: 100,
+            ..GameConfig::default()
+        };
+        let mut game = Game::with_config(1, config);
+        for handle in 1..=300 {
+            game.add(handle);
+            let position = game.get_random_location();
+            let angle = game.rng.gen_range(0., std::f32::consts::PI * 2.);
+            let player = game.players.get_mut(&handle).unwrap();
+            player.position = position;
+            player.direction = Vector::new(angle.cos(), angle.sin());
+        }
+        game.step();
+        assert!(game.collectibles.len() > 4000);
+        game.update_index();
+        assert!(!game.player_collisions().is_empty());
+        // a single game stepped in place, nothing but the step is timed
+        b.iter(|| game.step());
+    }
+}
+
+#[cfg(test)]
+mod test_prediction {
+    use super::*;
+
+    fn still_board() -> Game {
+        let config = GameConfig {
+            collectible_density: 0.,
+            hazard_count: 0,
+            ..GameConfig::default()
+        };
+        let mut game = Game::with_config(1, config);
+        game.add(1);
+        game
+    }
+
+    #[test]
+    fn test_unacknowledged_inputs_are_replayed() {
+        let mut server = still_board();
+        let mut client = server.clone();
+        client.active_player = Some(1);
+
+        // right for two ticks, then down for two more
+        let right = client.to_input_message(&vec![(1, UserInput::Right)]).unwrap();
+        client.handle_inputs(right.inputs.clone());
+        client.predict(2);
+        let down = client.to_input_message(&vec![(1, UserInput::Down)]).unwrap();
+        client.handle_inputs(down.inputs.clone());
+        client.predict(2);
+        assert_eq!((right.sequence, down.sequence), (1, 2));
+        assert_eq!((right.game_clock, down.game_clock), (0, 2));
+        let predicted = client.players[&1].position;
+
+        // the server only got to the first message so far
+        server.handle_input_message(1, &right);
+        server.step();
+        server.step();
+        client.update_state(Snapshot::Full(server.clone()));
+        assert_eq!(client.pending_inputs.len(), 1);
+        assert_eq!(client.predicted_clock, 4);
+        assert_eq!(client.players[&1].position, predicted);
+
+        server.handle_input_message(1, &down);
+        server.step();
+        server.step();
+        assert_eq!(server.players[&1].position, predicted);
+        client.update_state(Snapshot::Full(server.clone()));
+        assert!(client.pending_inputs.is_empty());
+        assert_eq!(client.players[&1].position, predicted);
+    }
+
+    #[test]
+    fn test_render_clock_moves_with_the_time_since_the_snapshot() {
+        let config = GameConfig {
+            tick_rate: 30,
+            snapshot_rate: 10,
+            ..GameConfig::default()
+        };
+        let mut server = Game::with_config(1, config);
+        server.game_clock = 30;
+        let mut client = Game::default();
+        client.update_state(Snapshot::Full(server.clone()));
+        assert_eq!(client.render_clock(), 27.);
+        client.since_snapshot = 0.5;
+        assert_eq!(client.render_clock(), 42.);
+
+        server.game_clock = 33;
+        client.update_state(Snapshot::Full(server));
+        assert_eq!(client.render_clock(), 30.);
+    }
+
+    #[test]
+    fn test_prediction_runs_a_round_trip_ahead() {
+        let mut server = still_board();
+        let mut client = server.clone();
+        client.active_player = Some(1);
+
+        let right = client.to_input_message(&vec![(1, UserInput::Right)]).unwrap();
+        client.handle_inputs(right.inputs.clone());
+        client.predict(1);
+        // the message takes three ticks to arrive
+        for _tick in 0..3 {
+            server.step();
+        }
+        server.handle_input_message(1, &right);
+        server.step();
+        client.update_state(Snapshot::Full(server.clone()));
+
+        // later inputs are expected to land as late, the prediction moved ahead to match
+        assert_eq!(client.predicted_clock, 4);
+        assert_eq!(client.players[&1].position, server.players[&1].position);
+        let down = client.to_input_message(&vec![(1, UserInput::Down)]).unwrap();
+        assert_eq!(down.game_clock, 4);
+    }
+
+    #[test]
+    fn test_inputs_only_steer_their_owner() {
+        let mut server = Game::with_seed(1);
+        server.add(1);
+        server.add(2);
+        let forged = InputMessage::new(vec![(1, UserInput::Right), (2, UserInput::Down)], 1, 1);
+        server.handle_input_message(2, &forged);
+
+        assert_eq!(server.players[&1].direction, Vector::new(0., 0.));
+        assert_eq!(server.players[&1].input_sequence, 0);
+        assert_eq!(server.players[&2].direction, Vector::new(0., 1.));
+        assert_eq!(server.players[&2].input_sequence, 1);
+    }
+}
+
+#[cfg(test)]
+mod test_join {
+    use super::*;
+
+    #[test]
+    fn test_names_are_validated() {
+        let mut game = Game::new();
+        assert_eq!(game.join(1, "  Bear_01 ", Rgb::BLUE), Ok(1));
+        assert_eq!(game.players[&1].name, "Bear_01");
+        assert_eq!(game.players[&1].color, Rgb::BLUE);
+
+        assert_eq!(game.join(2, "bear_01", Rgb::RED), Err(JoinRejection::NameTaken));
+        assert_eq!(game.join(2, "   ", Rgb::RED), Err(JoinRejection::NameTooShort));
+        assert_eq!(
+            game.join(2, "a name that is way too long", Rgb::RED),
+            Err(JoinRejection::NameTooLong)
+        );
+        assert_eq!(game.join(2, "<script>", Rgb::RED), Err(JoinRejection::InvalidCharacters));
+        assert!(!game.players.contains_key(&2));
+    }
+}
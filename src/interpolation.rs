@@ -0,0 +1,150 @@
+use crate::boundary::BoundaryPolicy;
+use crate::game::{Game, PlayerHandle};
+use quicksilver::geom::Vector;
+use std::collections::{HashMap, VecDeque};
+
+/// Positions of the players in a single snapshot received from the server.
+#[derive(Debug, Clone)]
+struct Sample {
+    game_clock: u32,
+    positions: HashMap<PlayerHandle, Vector>,
+}
+
+/// Recent snapshots kept on the client, so that remote players move smoothly
+/// between the (rather rare) updates instead of jumping from one to another.
+#[derive(Debug, Clone)]
+pub struct InterpolationBuffer {
+    capacity: usize,
+    samples: VecDeque<Sample>,
+    /// how the board of the latest snapshot treats its edges, players move across them when it wraps
+    boundary: BoundaryPolicy,
+    game_size: Vector,
+    /// longest extrapolation, in ticks of the latest snapshot's tick rate
+    max_extrapolation: f32,
+}
+
+impl Default for InterpolationBuffer {
+    fn default() -> Self {
+        Self::new(crate::config::INTERPOLATION_BUFFER)
+    }
+}
+
+impl InterpolationBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+            boundary: BoundaryPolicy::default(),
+            game_size: Vector::ZERO,
+            max_extrapolation: 0.,
+        }
+    }
+
+    pub fn push(&mut self, game: &Game) {
+        if let Some(newest) = self.samples.back() {
+            // out of order or repeated snapshot, nothing new to learn from it
+            if newest.game_clock >= game.game_clock {
+                return;
+            }
+        }
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.boundary = game.boundary;
+        self.game_size = game.game_size;
+        self.max_extrapolation = game.config.ticks(crate::config::MAX_EXTRAPOLATION) as f32;
+        self.samples.push_back(Sample {
+            game_clock: game.game_clock,
+            positions: game
+                .players
+                .iter()
+                .map(|(&handle, player)| (handle, player.position))
+                .collect(),
+        });
+    }
+
+    /// Where the player should be drawn at `render_clock`.
+    ///
+    /// Interpolates between the snapshots surrounding it, or extrapolates along the last known
+    /// movement when the snapshots are late, but never further than `MAX_EXTRAPOLATION`.
+    pub fn position(&self, handle: PlayerHandle, render_clock: f32) -> Option<Vector> {
+        let samples: Vec<(f32, Vector)> = self
+            .samples
+            .iter()
+            .filter_map(|sample| Some((sample.game_clock as f32, *sample.positions.get(&handle)?)))
+            .collect();
+        let (&(last_clock, last), rest) = samples.split_last()?;
+        let &(previous_clock, previous) = match rest.last() {
+            Some(sample) => sample,
+            None => return Some(last),
+        };
+        if render_clock <= samples[0].0 {
+            return Some(samples[0].1);
+        }
+        if let Some(window) = samples
+            .windows(2)
+            .find(|window| window[0].0 <= render_clock && render_clock <= window[1].0)
+        {
+            let ((from_clock, from), (to_clock, to)) = (window[0], window[1]);
+            let progress = (render_clock - from_clock) / (to_clock - from_clock);
+            return Some(self.moved(from, self.offset(from, to) * progress));
+        }
+        let ahead = (render_clock - last_clock).min(self.max_extrapolation);
+        let movement = self.offset(previous, last) * (ahead / (last_clock - previous_clock));
+        Some(self.moved(last, movement))
+    }
+
+    fn offset(&self, from: Vector, to: Vector) -> Vector {
+        self.boundary.offset(from, to, self.game_size)
+    }
+
+    fn moved(&self, position: Vector, movement: Vector) -> Vector {
+        self.boundary.wrapped(position + movement, self.game_size)
+    }
+}
+
+#[cfg(test)]
+mod test_interpolation {
+    use super::*;
+    use crate::config::GameConfig;
+
+    fn snapshot(game_clock: u32, x: f32) -> Game {
+        let mut game = Game::new();
+        game.add(1);
+        game.players.get_mut(&1).unwrap().position = Vector::new(x, 0.);
+        game.game_clock = game_clock;
+        game
+    }
+
+    #[test]
+    fn test_positions_between_and_after_snapshots() {
+        let mut buffer = InterpolationBuffer::new(3);
+        assert_eq!(buffer.position(1, 10.), None);
+        buffer.push(&snapshot(10, 0.));
+        assert_eq!(buffer.position(1, 12.), Some(Vector::new(0., 0.)));
+        buffer.push(&snapshot(13, 30.));
+
+        assert_eq!(buffer.position(1, 5.), Some(Vector::new(0., 0.)));
+        assert_eq!(buffer.position(1, 11.5), Some(Vector::new(15., 0.)));
+        assert_eq!(buffer.position(1, 14.), Some(Vector::new(40., 0.)));
+        // late snapshots don't send players flying off forever
+        let limit = 30. + 10. * GameConfig::default().ticks(crate::config::MAX_EXTRAPOLATION) as f32;
+        assert_eq!(buffer.position(1, 1000.), Some(Vector::new(limit, 0.)));
+    }
+
+    #[test]
+    fn test_wrapping_players_move_across_the_edge() {
+        let wrapping = |game_clock, x| {
+            let mut game = snapshot(game_clock, x);
+            game.boundary = BoundaryPolicy::Wrap;
+            game.game_size = Vector::new(100., 100.);
+            game
+        };
+        let mut buffer = InterpolationBuffer::new(3);
+        buffer.push(&wrapping(10, 95.));
+        buffer.push(&wrapping(12, 5.));
+        assert_eq!(buffer.position(1, 11.), Some(Vector::new(0., 0.)));
+        assert_eq!(buffer.position(1, 11.5), Some(Vector::new(2.5, 0.)));
+        assert_eq!(buffer.position(1, 13.), Some(Vector::new(10., 0.)));
+    }
+}
@@ -1,10 +1,19 @@
 #![feature(async_closure)]
+#![cfg_attr(test, feature(test))]
 
+pub mod boundary;
+pub mod cell;
 pub mod communication;
 pub mod config;
+pub mod controls;
 pub mod game;
+pub mod interpolation;
 pub mod rendering;
 pub mod obstacles;
+pub mod random;
+pub mod snapshot;
+pub mod spatial;
+pub mod spawner;
 
 use quicksilver::geom::Vector;
 use std::rc::Rc;
@@ -23,7 +32,6 @@ use serde::{Serialize, Deserialize};
 use wasm_bindgen::JsCast;
 use web_sys::{ErrorEvent, MessageEvent, WebSocket};
 
-use serde_json::{from_str, to_string};
 
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "quicksilver::geom::Vector")]
@@ -57,147 +65,339 @@ fn get_host() -> Option<String> {
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+/// The socket to the server, along with the encoding the server accepted for it.
+#[derive(Clone)]
+struct Connection {
+    /// replaced by a new socket whenever our session is resumed
+    ws: Rc<RefCell<Option<WebSocket>>>,
+    /// json until the first server frame shows otherwise, every server reads json
+    encoding: Rc<core::cell::Cell<communication::Encoding>>,
+    /// our player and the token taking it back once the socket closes, from `HelloPlayer`
+    session: Rc<RefCell<Option<(game::PlayerHandle, String)>>>,
+    /// when the socket closed, the server only keeps our player for the grace period after
+    lost_at: Rc<core::cell::Cell<Option<f64>>>,
+}
+
+impl Connection {
+    fn new() -> Self {
+        Self {
+            ws: Rc::new(RefCell::new(None)),
+            encoding: Rc::new(core::cell::Cell::new(communication::Encoding::Json)),
+            session: Rc::new(RefCell::new(None)),
+            lost_at: Rc::new(core::cell::Cell::new(None)),
+        }
+    }
+
+    /// Remembers the encoding the server answers in, and sends everything after in it too.
+    fn negotiated(&self, frame: &communication::Frame) {
+        self.encoding.set(frame.encoding());
+    }
+
+    /// Address of the game, the one resuming our session when there is one.
+    fn url(&self, host: &str) -> String {
+        let resume = match self.session.borrow().as_ref() {
+            Some((player_handle, token)) => format!("{}?token={}&", player_handle, token),
+            None => "?".to_string(),
+        };
+        format!(
+            "ws://{}/game/{}encoding={}&version={}",
+            host,
+            resume,
+            communication::Encoding::preferred().as_str(),
+            communication::PROTOCOL_VERSION
+        )
+    }
+}
+
+fn send_message(connection: &Connection, message: &communication::ClientMessage) -> Result<(), JsValue> {
+    let frame = connection
+        .encoding
+        .get()
+        .encode(message)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let ws = connection.ws.borrow();
+    let ws = ws.as_ref().ok_or_else(|| JsValue::from_str("not connected"))?;
+    match frame {
+        communication::Frame::Text(text) => ws.send_with_str(&text),
+        communication::Frame::Binary(mut bytes) => ws.send_with_u8_array(&mut bytes),
+    }
+}
+
+/// Asks the player for a name and picks a random color for them.
+fn join_message(question: &str) -> communication::ClientMessage {
+    let name = web_sys::window()
+        .and_then(|window| window.prompt_with_message(question).ok())
+        .flatten()
+        .unwrap_or_default();
+    let random_channel = || (js_sys::Math::random() * 255.) as u8;
+    communication::ClientMessage::Join {
+        name,
+        color: rendering::Rgb {
+            r: random_channel(),
+            g: random_channel(),
+            b: random_channel(),
+        },
+        protocol_version: communication::PROTOCOL_VERSION,
+    }
+}
+
 type ClientGameState = Rc<RefCell<game::Game>>;
-static render_size: Vector = Vector { x: 500.0, y: 500.0 };
-// This is like the `main` function, except for JavaScript.
-#[wasm_bindgen(start)]
-pub fn main_js() -> Result<(), JsValue> {
-    // This provides better error messages in debug mode.
-    // It's disabled in release mode so it doesn't bloat up the file size.
-    #[cfg(debug_assertions)]
-    console_error_panic_hook::set_once();
 
-    async fn app(window: Window, mut gfx: Graphics, mut input: Input) -> QsResult<()> {
-        let game_state: ClientGameState = Default::default();
-        let game_state_clone_1 = Rc::clone(&game_state);
-        let game_state_clone_2 = Rc::clone(&game_state);
-        let default_host = get_host()
-            .or(Some(config::BACKEND_ADDRESS.to_string()))
-            .expect("we always pick a backend server");
-        let update_state = || move |txt: String| {
-            Rc::clone(&game_state_clone_1).borrow_mut().update_state(txt);
+/// Opens a socket to the server and handles whatever it sends, resuming our session when there is one.
+fn connect(connection: &Connection, game_state: &ClientGameState) -> Result<(), JsValue> {
+    let host = get_host().unwrap_or_else(|| config::BACKEND_ADDRESS.to_string());
+    let ws = WebSocket::new(&connection.url(&host))?;
+    // For small binary messages, like CBOR, Arraybuffer is more efficient than Blob handling
+    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+    // a new socket negotiates its encoding again
+    connection.encoding.set(communication::Encoding::Json);
+    connection.ws.replace(Some(ws.clone()));
+    // create callback
+    let (message_connection, message_game_state) = (connection.clone(), Rc::clone(game_state));
+    let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
+        // Handle difference Text/Binary,...
+        let frame = if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+            Some(communication::Frame::Binary(js_sys::Uint8Array::new(&abuf).to_vec()))
+        } else if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
+            console_log!("new message: {:?}", txt);
+            txt.as_string().map(communication::Frame::Text)
+        } else {
+            console_log!("message event, received Unknown: {:?}", e.data());
+            None
         };
-        let ws = WebSocket::new(format!("ws://{}/game/", default_host).as_str())
-            .expect("failed to connect to ws server");
-        // For small binary messages, like CBOR, Arraybuffer is more efficient than Blob handling
-        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
-        // create callback
-        let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
-            // // Handle difference Text/Binary,...
-            // if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
-            //     console_log!("message event, received arraybuffer: {:?}", abuf);
-            //     let array = js_sys::Uint8Array::new(&abuf);
-            //     let len = array.byte_length() as usize;
-            //     console_log!("Arraybuffer received {}bytes: {:?}", len, array.to_vec());
-            //     // here you can for example use Serde Deserialize decode the message
-            //     // for demo purposes we switch back to Blob-type and send off another binary message
-            //     cloned_ws.set_binary_type(web_sys::BinaryType::Blob);
-            //     match cloned_ws.send_with_u8_array(&vec![5, 6, 7, 8]) {
-            //         Ok(_) => console_log!("binary message successfully sent"),
-            //         Err(err) => console_log!("error sending message: {:?}", err),
-            //     }
-            // } else if let Ok(blob) = e.data().dyn_into::<web_sys::Blob>() {
-            //     console_log!("message event, received blob: {:?}", blob);
-            //     // better alternative to juggling with FileReader is to use https://crates.io/crates/gloo-file
-            //     let fr = web_sys::FileReader::new().unwrap();
-            //     let fr_c = fr.clone();
-            //     // create onLoadEnd callback
-            //     let onloadend_cb = Closure::wrap(Box::new(move |_e: web_sys::ProgressEvent| {
-            //         let array = js_sys::Uint8Array::new(&fr_c.result().unwrap());
-            //         let len = array.byte_length() as usize;
-            //         console_log!("Blob received {}bytes: {:?}", len, array.to_vec());
-            //         // here you can for example use the received image/png data
-            //     })
-            //         as Box<dyn FnMut(web_sys::ProgressEvent)>);
-            //     fr.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
-            //     fr.read_as_array_buffer(&blob).expect("blob not readable");
-            //     onloadend_cb.forget(); }
-            if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                console_log!("new message: {:?}", txt);
-                if let Some(message_str) = txt.as_string() {
-                    console_log!("received a message: {:?}", message_str);
-                    if let Ok(hello_message) = from_str::<communication::ServerMessage>(&message_str) {
-                        match hello_message {
-                            communication::ServerMessage::HelloPlayer(new_player_handle, game_state) => {
-                                *game_state_clone_2.borrow_mut() = game_state;
-                                game_state_clone_2.borrow_mut().active_player = Some(new_player_handle);
-                                console_log!("connected as [#{}]", new_player_handle);
+        let (connection, game_state) = (&message_connection, &message_game_state);
+        if let Some(frame) = frame {
+            connection.negotiated(&frame);
+            match frame.decode::<communication::ServerMessage>() {
+                Ok(server_message) => {
+                    match server_message {
+                        communication::ServerMessage::HelloPlayer(new_player_handle, resume_token, game_view) => {
+                            // kept to take our player back if the socket closes
+                            connection.session.replace(resume_token.map(|token| (new_player_handle, token)));
+                            let mut state = game_state.borrow_mut();
+                            // keep the baselines around, the server keeps sending deltas against them
+                            let baselines = std::mem::take(&mut state.baselines);
+                            *state = game_view;
+                            state.active_player = Some(new_player_handle);
+                            state.baselines = baselines;
+                            console_log!("connected as [#{}]", new_player_handle);
+                            let ping = communication::ClientMessage::Ping { timestamp: js_sys::Date::now() };
+                            if let Err(err) = send_message(connection, &ping) {
+                                console_log!("error sending message: {:?}", err);
+                            }
+                        }
+                        communication::ServerMessage::JoinRejected(reason) => {
+                            console_log!("could not join: {}", reason);
+                            let question = format!("Could not join, {}. Pick another name", reason);
+                            if let Err(err) = send_message(connection, &join_message(&question)) {
+                                console_log!("error sending message: {:?}", err);
+                            }
+                        }
+                        communication::ServerMessage::PlayerLeft(player_handle) => {
+                            game_state.borrow_mut().remove(&player_handle);
+                            console_log!("player [#{}] left the game", player_handle);
+                        }
+                        communication::ServerMessage::State(snapshot) => {
+                            let applied = game_state.borrow_mut().update_state(snapshot);
+                            if let Some(game_clock) = applied {
+                                let ack = communication::ClientMessage::Ack(communication::SnapshotAck { game_clock });
+                                if let Err(err) = send_message(connection, &ack) {
+                                    console_log!("failed to acknowledge snapshot: {:?}", err);
+                                }
+                            }
+                        }
+                        communication::ServerMessage::Pong { timestamp } => {
+                            console_log!("round trip time: {}ms", js_sys::Date::now() - timestamp);
+                        }
+                        communication::ServerMessage::Chat { from, name, text } => {
+                            console_log!("[#{}] {}: {}", from, name, text);
+                        }
+                        communication::ServerMessage::Error(reply) => {
+                            console_log!("server refused the last message: {}", reply);
+                            if reply == communication::ErrorReply::InvalidResumeToken {
+                                // our player is gone for good, nothing to reconnect to
+                                connection.session.replace(None);
                             }
                         }
                     }
-
-                    (update_state.clone()())(message_str);
                 }
-
-            } else {
-                console_log!("message event, received Unknown: {:?}", e.data());
+                Err(e) => console_log!("failed to decode server message: {}", e),
             }
-        }) as Box<dyn FnMut(MessageEvent)>);
-        // set message event handler on WebSocket
-        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-        // forget the callback to keep it alive
-        onmessage_callback.forget();
-
-        let onerror_callback = Closure::wrap(Box::new(move |e: ErrorEvent| {
-            console_log!("error event: {:?}", e);
-        }) as Box<dyn FnMut(ErrorEvent)>);
-        ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
-        onerror_callback.forget();
-
-        let cloned_ws = ws.clone();
-        let onopen_callback = Closure::wrap(Box::new(move |_| {
-            console_log!("socket opened");
-            match cloned_ws.send_with_str("ping") {
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    // set message event handler on WebSocket
+    ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+    // forget the callback to keep it alive
+    onmessage_callback.forget();
+
+    let onerror_callback = Closure::wrap(Box::new(move |e: ErrorEvent| {
+        console_log!("error event: {:?}", e);
+    }) as Box<dyn FnMut(ErrorEvent)>);
+    ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+    onerror_callback.forget();
+
+
+    let open_connection = connection.clone();
+    let onopen_callback = Closure::wrap(Box::new(move |_| {
+        console_log!("socket opened");
+        open_connection.lost_at.set(None);
+        // a resumed session already has its player on the board
+        let joining = open_connection.session.borrow().is_none();
+        if joining {
+            match send_message(&open_connection, &join_message("Pick a name")) {
                 Ok(_) => console_log!("message successfully sent"),
                 Err(err) => console_log!("error sending message: {:?}", err),
             }
-            // send off binary message
-        }) as Box<dyn FnMut(JsValue)>);
-        ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-        onopen_callback.forget();
+        }
+    }) as Box<dyn FnMut(JsValue)>);
+    ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+    onopen_callback.forget();
+
+    let (close_connection, close_game_state) = (connection.clone(), Rc::clone(game_state));
+    let onclose_callback = Closure::wrap(Box::new(move |_| {
+        console_log!("socket closed");
+        reconnect(&close_connection, &close_game_state);
+    }) as Box<dyn FnMut(JsValue)>);
+    ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+    onclose_callback.forget();
+    Ok(())
+}
+
+/// Opens the socket again while the server still keeps our player, giving up once the grace period is over.
+fn reconnect(connection: &Connection, game_state: &ClientGameState) {
+    if connection.session.borrow().is_none() {
+        console_log!("disconnected from the server");
+        return;
+    }
+    let now = js_sys::Date::now();
+    let lost_at = connection.lost_at.get().unwrap_or(now);
+    connection.lost_at.set(Some(lost_at));
+    let grace_period = game_state.borrow().config.disconnect_grace_period as f64 * 1000.;
+    if now - lost_at >= grace_period {
+        console_log!("could not reconnect in time, our player is gone");
+        connection.session.replace(None);
+        return;
+    }
+    // the first attempt goes right away, the next ones give a restarting server some time
+    let delay = if now == lost_at { 0. } else { config::RECONNECT_INTERVAL * 1000. };
+    let (connection, game_state) = (connection.clone(), Rc::clone(game_state));
+    let attempt = Closure::once_into_js(move || {
+        if let Err(err) = connect(&connection, &game_state) {
+            console_log!("failed to reconnect: {:?}", err);
+            reconnect(&connection, &game_state);
+        }
+    });
+    let scheduled = web_sys::window().map(|window| {
+        window.set_timeout_with_callback_and_timeout_and_arguments_0(
+            attempt.unchecked_ref(),
+            delay as i32,
+        )
+    });
+    if let Some(Err(err)) = scheduled {
+        console_log!("failed to schedule a reconnection: {:?}", err);
+    }
+}
+
+/// Screen transform centered on our player, zoomed out as it grows.
+fn camera(game: &game::Game) -> Transform {
+    let (proportion, new_center) = match game.get_player() {
+        Some(player) => (player.zoom(), player.centroid()),
+        None => (1.0, Vector::ZERO),
+    };
+    let scale = Transform::scale(Vector::ONE * proportion);
+    let center = Transform::translate(render_size / 2.0);
+    let player_position = Transform::translate(-new_center);
+    center * scale * player_position
+}
+
+static render_size: Vector = Vector {
+    x: config::VIEWPORT_WIDTH,
+    y: config::VIEWPORT_HEIGHT,
+};
+// This is like the `main` function, except for JavaScript.
+#[wasm_bindgen(start)]
+pub fn main_js() -> Result<(), JsValue> {
+    // This provides better error messages in debug mode.
+    // It's disabled in release mode so it doesn't bloat up the file size.
+    #[cfg(debug_assertions)]
+    console_error_panic_hook::set_once();
+
+    async fn app(window: Window, mut gfx: Graphics, mut input: Input) -> QsResult<()> {
+        let game_state: ClientGameState = Default::default();
+        let connection = Connection::new();
+        connect(&connection, &game_state).expect("failed to connect to ws server");
 
         // Your code goes here!
         // console::log_1(&JsValue::from_str("Hello world!"));
 
+        let mut last_steering = None;
+        // our player moves a server tick at a time, however fast the screen refreshes
+        let mut last_frame = js_sys::Date::now();
+        let mut unsimulated = 0.;
+        let mut key_bindings = controls::KeyBindings::load();
+        let mut rebinding = controls::Rebinding::default();
+        let mut gamepad = controls::GamepadControls::default();
         // Clear the screen to a blank, white color
         loop {
-            let ws = ws.clone();
-            while let Some(_) = input.next_event().await {}
-            // game_state.lock().unwrap().handle_quicksilver_input(&mut input, player_handle);
-            let player_inputs = game_state.borrow_mut().get_player_input(&mut input);
-
-            if let Some(client_message) = game_state.borrow().to_client_message(&player_inputs) {
-                let serialized_output = &to_string(&client_message).expect("failed to serialize user inputs");
-
-                if let Ok(_) = ws.send_with_str(
-                    serialized_output
-                ) {} else {
-                    console_log!("failed to send the input");
+            let ws = connection.clone();
+            while let Some(event) = input.next_event().await {
+                gamepad.handle_event(&event);
+                let waiting_for = rebinding.waiting_for();
+                if rebinding.handle_event(&mut key_bindings, &event) {
+                    match key_bindings.save() {
+                        Ok(_) => console_log!("key bindings saved"),
+                        Err(err) => console_log!("failed to save the key bindings: {:?}", err),
+                    }
+                }
+                if rebinding.waiting_for() != waiting_for {
+                    if let Some(action) = rebinding.waiting_for() {
+                        console_log!("press the key for {:?}", action);
                     }
+                }
             }
+            let now = js_sys::Date::now();
+            let frame_time = now - last_frame;
+            last_frame = now;
+            game_state.borrow_mut().since_snapshot += (frame_time / 1000.) as f32;
+            let tick = 1000. / game_state.borrow().config.tick_rate as f64;
+            unsimulated = (unsimulated + frame_time).min(tick * config::MAX_CATCH_UP_TICKS as f64);
+            let ticks = (unsimulated / tick) as u32;
+            unsimulated -= ticks as f64 * tick;
 
+            // inputs are read and sent once per server tick, fast screens don't flood the server
+            if ticks > 0 {
+                let mut player_inputs = game_state
+                    .borrow_mut()
+                    .get_player_input(&input, &key_bindings, &gamepad);
+                // keys and gamepads win, the mouse steers whenever nothing else moves the player
+                if player_inputs.iter().any(|(_handle, player_input)| player_input.is_movement()) {
+                    // the keys took over the direction, the mouse gets it back once they're released
+                    last_steering = None;
+                } else {
+                    let steering = game_state
+                        .borrow()
+                        .steering_input(camera(&game_state.borrow()), input.mouse().location());
+                    // only sent when the mouse moves, the direction sticks on the server
+                    if steering != last_steering {
+                        player_inputs.extend(steering);
+                        last_steering = steering;
+                    }
+                }
 
-            game_state.borrow_mut().handle_inputs(player_inputs);
-            game_state.borrow_mut().step();
+                let input_message = game_state.borrow_mut().to_input_message(&player_inputs);
+                if let Some(input_message) = input_message {
+                    if let Err(err) = send_message(&ws, &communication::ClientMessage::Input(input_message)) {
+                        console_log!("failed to send the input: {:?}", err);
+                    }
+                }
+                game_state.borrow_mut().handle_inputs(player_inputs);
+            }
+            game_state.borrow_mut().predict(ticks);
             gfx.clear(Color::WHITE);
             // Paint a blue square with a red outline in the center of our screen
             // It should have a top-left of (350, 100) and a size of (150, 100)
 
-            let proportion = match game_state.borrow().get_player() {
-                Some(player) => crate::config::PLAYER_MIN_SIZE / player.size,
-                None => 1.0,
-            };
-
-            let new_center = match game_state.borrow().get_player() {
-                Some(player) => player.position.clone(),
-                None => Vector::ZERO,
-            };
-
-
-            let scale = Transform::scale(Vector::ONE * proportion);
-            let center = Transform::translate(render_size / 2.0);
-            let player_positoin = Transform::translate(-new_center);
-            gfx.set_transform(center * scale * player_positoin);
+            gfx.set_transform(camera(&game_state.borrow()));
 
             game_state.borrow().render(&mut gfx);
             // Send the data to be drawn
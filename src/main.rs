@@ -1,10 +1,23 @@
 #![feature(async_closure)]
+#![cfg_attr(test, feature(test))]
 
+mod boundary;
+mod cell;
+mod cli;
 mod communication;
 mod config;
+mod controls;
 mod game;
+mod interpolation;
 mod rendering;
 mod obstacles;
+mod random;
+mod scheduler;
+mod settings;
+mod simulation;
+mod snapshot;
+mod spatial;
+mod spawner;
 
 // #![deny(warnings)]
 use std::collections::HashMap;
@@ -16,7 +29,6 @@ use std::sync::{
 use game::VectorDef;
 
 use futures_new::{FutureExt, StreamExt};
-use serde_json::{from_str, to_string};
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, RwLock};
 use warp::ws::{Message, WebSocket};
@@ -25,64 +37,403 @@ use warp::Filter;
 /// Our global unique user id counter.
 static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 
+/// What a connected user is doing in the game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    /// connected, but neither playing nor watching yet
+    Connected,
+    /// has a player on the board
+    Playing,
+    /// watches the board through a camera of their choice, without a player
+    Spectating(game::Camera),
+}
+
+impl Role {
+    /// Snapshots are only sent to users who play or watch,
+    /// showing what is around their player or in front of their camera.
+    fn camera(self, uid: usize) -> Option<game::Camera> {
+        match self {
+            Role::Connected => None,
+            Role::Playing => Some(game::Camera::Follow(uid)),
+            Role::Spectating(camera) => Some(camera),
+        }
+    }
+}
+
+/// A single connected user.
+struct User {
+    /// sender of `warp::ws::Message`
+    tx: mpsc::UnboundedSender<Result<Message, warp::Error>>,
+    /// game clock of the last snapshot the user confirmed receiving
+    acknowledged: Option<u32>,
+    role: Role,
+    /// wire format negotiated when connecting
+    encoding: communication::Encoding,
+}
+
+impl User {
+    fn send(&self, message: &communication::ServerMessage) {
+        let encoded = self.encoding.encode(message).unwrap_or_else(|e| {
+            panic!("failed to serialize server message: {:#?}: {}", message, e)
+        });
+        let message = match encoded {
+            communication::Frame::Text(text) => Message::text(text),
+            communication::Frame::Binary(bytes) => Message::binary(bytes),
+        };
+        if let Err(_disconnected) = self.tx.send(Ok(message)) {
+            // The tx is disconnected, our `user_disconnected` code
+            // should be happening in another task, nothing more to
+            // do here.
+        }
+    }
+}
+
 /// Our state of currently connected users.
 ///
 /// - Key is their id
-/// - Value is a sender of `warp::ws::Message`
-type Users = Arc<RwLock<HashMap<usize, mpsc::UnboundedSender<Result<Message, warp::Error>>>>>;
+/// - Value is the user's connection
+type Users = Arc<RwLock<HashMap<usize, User>>>;
 pub type GameState = Arc<RwLock<game::Game>>;
 
-async fn run_game(game: GameState) {
+/// What it takes for a player whose websocket went away to reconnect.
+#[derive(Debug, Default)]
+struct Sessions {
+    /// secret handed out with `HelloPlayer`, key is the user id
+    tokens: HashMap<usize, String>,
+    /// players who may still reconnect, with the moment they disconnected
+    disconnected: HashMap<usize, Instant>,
+}
+
+type SharedSessions = Arc<RwLock<Sessions>>;
+
+/// A fresh secret for resuming a session, unguessable unlike the user ids.
+fn new_resume_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// A client message waiting to be applied at the start of the next tick.
+#[derive(Debug)]
+struct QueuedInput {
+    player: usize,
+    received: Instant,
+    message: communication::InputMessage,
+}
+
+/// Sending side of the queue feeding client inputs into `run_game`.
+type InputQueue = mpsc::UnboundedSender<QueuedInput>;
+
+/// How often a single connection may send inputs and chat messages.
+struct Limits {
+    inputs: scheduler::RateLimiter,
+    chat: scheduler::RateLimiter,
+}
+
+impl Limits {
+    fn new(tick_rate: u64, start: Instant) -> Self {
+        Self {
+            inputs: scheduler::RateLimiter::new(
+                config::MAX_INPUTS_PER_TICK * tick_rate as u32,
+                config::MAX_INPUT_BURST,
+                start,
+            ),
+            chat: scheduler::RateLimiter::new(
+                config::MAX_CHAT_PER_SECOND,
+                config::MAX_CHAT_BURST,
+                start,
+            ),
+        }
+    }
+}
+
+/// Why the server closed a connection.
+#[derive(Debug)]
+enum Kick {
+    /// sent inputs for a player they don't own
+    Impersonation { claimed: usize },
+}
+
+impl std::fmt::Display for Kick {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kick::Impersonation { claimed } => write!(f, "tried to steer player [#{}]", claimed),
+        }
+    }
+}
+
+/// Applies all inputs received since the last tick, oldest first.
+fn apply_inputs(game: &mut game::Game, inputs: &mut mpsc::UnboundedReceiver<QueuedInput>) {
+    let mut queued = vec![];
+    while let Ok(input) = inputs.try_recv() {
+        queued.push(input);
+    }
+    queued.sort_by_key(|input| (input.received, input.player, input.message.sequence));
+    for input in queued {
+        game.handle_input_message(input.player, &input.message);
+    }
+}
+
+async fn run_game(
+    game: GameState,
+    users: Users,
+    mut inputs: mpsc::UnboundedReceiver<QueuedInput>,
+) {
+    let rules = game.read().await.config.clone();
+    let ticks_per_snapshot = rules.ticks_per_snapshot();
+    let mut history = snapshot::SnapshotHistory::new(config::SNAPSHOT_HISTORY);
+    let mut timestep =
+        scheduler::FixedTimestep::new(rules.tick_rate, config::MAX_CATCH_UP_TICKS, Instant::now());
     loop {
-        tokio::time::delay_for(Duration::from_millis(1000 / 60)).await;
-        game.write().await.step();
+        tokio::time::delay_until(tokio::time::Instant::from_std(timestep.next_tick())).await;
+        let started = Instant::now();
+        let due = timestep.due(started);
+        if due.dropped > 0 {
+            eprintln!(
+                "tick overrun: server fell behind, dropped {} ticks",
+                due.dropped
+            );
+        }
+
+        let mut state = game.write().await;
+        let mut snapshot_due = false;
+        for _tick in 0..due.run {
+            apply_inputs(&mut state, &mut inputs);
+            state.step();
+            snapshot_due |= state.game_clock % ticks_per_snapshot == 0;
+        }
+        if snapshot_due {
+            broadcast_state(&state, &mut history, &users).await;
+        }
+
+        let elapsed = started.elapsed();
+        if elapsed > timestep.tick() * due.run.max(1) {
+            eprintln!(
+                "tick overrun: {} ticks took {:?}, budget is {:?} per tick",
+                due.run,
+                elapsed,
+                timestep.tick()
+            );
+        }
+    }
+}
+
+/// Sends the authoritative game snapshot to every connected user,
+/// as a delta against the last snapshot they acknowledged whenever possible.
+async fn broadcast_state(
+    game: &game::Game,
+    history: &mut snapshot::SnapshotHistory,
+    users: &Users,
+) {
+    for (&uid, user) in users.read().await.iter() {
+        let camera = match user.role.camera(uid) {
+            Some(camera) => camera,
+            None => continue,
+        };
+        let message = communication::ServerMessage::State(history.snapshot_for(
+            camera,
+            user.acknowledged,
+            game,
+        ));
+        user.send(&message);
     }
+    history.push(game.clone());
+}
+
+async fn broadcast_message(message: &communication::ServerMessage, users: &Users) {
+    for (&_uid, user) in users.read().await.iter() {
+        user.send(message);
+    }
+}
+
+/// The arguments were wrong, or the settings they point to.
+const EXIT_USAGE: i32 = 2;
+/// The command ran, but failed.
+const EXIT_FAILURE: i32 = 1;
+
+fn load_settings(common: &cli::Common) -> settings::Settings {
+    settings::Settings::load(common.config.as_deref(), &common.overrides).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(EXIT_USAGE);
+    })
 }
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
 
+    let command = cli::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("error: {}\n\nsee --help for usage", e);
+        std::process::exit(EXIT_USAGE);
+    });
+    match command {
+        cli::Command::Serve(common) => {
+            let settings = load_settings(&common);
+            serve(settings, common.seed.unwrap_or_else(rand::random)).await
+        }
+        cli::Command::Simulate(common, options) => {
+            let settings = load_settings(&common);
+            simulate(settings.game, common.seed.unwrap_or_else(rand::random), options)
+        }
+        cli::Command::Replay(path) => replay(&path),
+        cli::Command::Help => print!("{}", cli::HELP),
+    }
+}
+
+fn simulate(config: config::GameConfig, seed: u64, options: cli::Simulate) {
+    println!(
+        "simulating {} ticks with {} players, seed {}",
+        options.ticks, options.players, seed
+    );
+    let started = Instant::now();
+    let recording = simulation::simulate(seed, config, options.players, options.ticks);
+    let elapsed = started.elapsed();
+    println!("{}", recording.stats);
+    println!(
+        "took {:?}, {:?} per tick",
+        elapsed,
+        elapsed / options.ticks.max(1)
+    );
+    if let Some(path) = options.record {
+        if let Err(e) = recording.save(&path) {
+            eprintln!("error: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+        println!("recorded to {}", path.display());
+    }
+}
+
+fn replay(path: &std::path::Path) {
+    let recording = simulation::Recording::load(path).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    });
+    let stats = recording.replay();
+    println!("{}", stats);
+    if stats != recording.stats {
+        eprintln!("error: the replay diverged, the recording ended with\n{}", recording.stats);
+        std::process::exit(EXIT_FAILURE);
+    }
+    println!("the replay matches the recording");
+}
+
+async fn serve(settings: settings::Settings, seed: u64) {
     // Keep track of all connected users, key is usize, value
     // is a websocket sender.
     let users = Users::default();
-    // Turn our "state" into a new Filter...
-    let users = warp::any().map(move || users.clone());
+    // Resume tokens, and players who lost their connection and are waiting to be removed.
+    let sessions = SharedSessions::default();
 
     // do the same for the game state
-    let game_state: GameState = Arc::new(RwLock::new(game::Game::new()));
+    let game_state: GameState =
+        Arc::new(RwLock::new(game::Game::with_config(seed, settings.game.clone())));
     let game_running_state = Arc::clone(&game_state);
-    let app = async || run_game(game_running_state).await;
+    let game_running_users = Arc::clone(&users);
+    // client inputs are queued and applied by the game loop, never straight from the sockets
+    let (input_queue, game_running_inputs) = mpsc::unbounded_channel();
+    let app = async || run_game(game_running_state, game_running_users, game_running_inputs).await;
 
     tokio::task::spawn(app());
 
+    // Turn our "state" into a new Filter...
+    let users = warp::any().map(move || users.clone());
     let game_state = warp::any().map(move || game_state.clone());
-    // GET /chat -> websocket upgrade
-    let chat = warp::path("game")
+    let sessions = warp::any().map(move || sessions.clone());
+    let input_queue = warp::any().map(move || input_queue.clone());
+    // GET /game/<id>?token=<token> -> resume a session that is still in its grace period
+    let resume = warp::path!("game" / usize).map(Some);
+    // GET /game -> join as a new player
+    let join = warp::path("game").map(|| None::<usize>);
+    // ?encoding=binary|json picks the wire format, json when missing
+    let params = warp::query::<communication::ConnectionParams>()
+        .or(warp::any().map(communication::ConnectionParams::default))
+        .unify();
+    let chat = resume
+        .or(join)
+        .unify()
+        .and(params)
         // The `ws()` filter will prepare Websocket handshake...
         .and(warp::ws())
         .and(users)
         .and(game_state)
-        .map(|ws: warp::ws::Ws, users, game_state| {
-            // This will call our function if the handshake succeeds.
-            ws.on_upgrade(move |socket| user_connected(socket, users, game_state))
-        });
+        .and(sessions)
+        .and(input_queue)
+        .map(
+            |resume_id: Option<usize>,
+             params: communication::ConnectionParams,
+             ws: warp::ws::Ws,
+             users,
+             game_state,
+             sessions,
+             input_queue| {
+                // This will call our function if the handshake succeeds.
+                ws.on_upgrade(move |socket| {
+                    user_connected(
+                        socket,
+                        resume_id,
+                        params,
+                        users,
+                        game_state,
+                        sessions,
+                        input_queue,
+                    )
+                })
+            },
+        );
 
     // GET / -> index html
     // let index = warp::path::end().map(|| warp::reply::html(INDEX_HTML));
     let index = warp::any()
-        .and(warp::fs::dir("dist/"));
+        .and(warp::fs::dir(settings.server.static_dir.clone()));
 
     let routes = chat.or(index);
 
-    warp::serve(routes).run(([0, 0, 0, 0], 80)).await;
+    let (address, server) = match warp::serve(routes).try_bind_ephemeral(settings.server.address()) {
+        Ok(bound) => bound,
+        Err(e) => {
+            eprintln!("error: can't listen on {}: {}", settings.server.address(), e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    println!("listening on {}, seed {}", address, seed);
+    server.await;
 }
 
-async fn user_connected(ws: WebSocket, users: Users, game_state: GameState) {
-    // Use a counter to assign a new unique ID for this user.
-    let my_id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
-    game_state.write().await.add(my_id);
-    println!("new player ID: {}", my_id);
+async fn user_connected(
+    ws: WebSocket,
+    resume_id: Option<usize>,
+    params: communication::ConnectionParams,
+    users: Users,
+    game_state: GameState,
+    sessions: SharedSessions,
+    input_queue: InputQueue,
+) {
+    let encoding = if params.encoding.is_supported() {
+        params.encoding
+    } else {
+        communication::Encoding::Json
+    };
+    // a session is only handed to whoever holds its token
+    let token_check = match resume_id {
+        Some(id) => params.check_token(sessions.read().await.tokens.get(&id)),
+        None => Ok(()),
+    };
+    let resumed = match (resume_id, &token_check) {
+        (Some(id), Ok(())) => {
+            sessions.write().await.disconnected.remove(&id).is_some()
+                && game_state.read().await.players.contains_key(&id)
+        }
+        _ => false,
+    };
+    let my_id = if resumed {
+        let my_id = resume_id.expect("resumed sessions always carry an id");
+        println!("player ID {} reconnected", my_id);
+        my_id
+    } else {
+        // Use a counter to assign a new unique ID for this user,
+        // their player is only added once they join.
+        let my_id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
+        println!("new connection ID: {}", my_id);
+        my_id
+    };
     // Split the socket into a sender and receive of messages.
     let (user_ws_tx, mut user_ws_rx) = ws.split();
 
@@ -96,23 +447,34 @@ async fn user_connected(ws: WebSocket, users: Users, game_state: GameState) {
     }));
 
     // Save the sender in our list of connected users.
-    users.write().await.insert(my_id, tx);
+    users.write().await.insert(
+        my_id,
+        User {
+            tx,
+            acknowledged: None,
+            role: if resumed { Role::Playing } else { Role::Connected },
+            encoding,
+        },
+    );
 
-    // for (&uid, tx) in users.read().await.iter() {
-    users
-        .read()
-        .await
-        .get(&my_id)
-        .expect("cannot find just inserted user...?")
-        .send(Ok(Message::text(
-            game_state.read().await.state_dump().clone(),
-        )));
+    // old clients, and anyone trying to take over a session, are told why before being let go
+    if let Err(reply) = params.check_version().and(token_check) {
+        println!("refusing connection ID {}: {}", my_id, reply);
+        send_message(my_id, &communication::ServerMessage::Error(reply), &users).await;
+        users.write().await.remove(&my_id);
+        if resumed {
+            user_disconnected(my_id, users, game_state, sessions).await;
+        }
+        return;
+    }
 
     // Return a `Future` that is basically a state machine managing
     // this specific user's connection.
 
     // Make an extra clone to give to our disconnection handler...
     let users2 = users.clone();
+    let tick_rate = game_state.read().await.config.tick_rate;
+    let mut limits = Limits::new(tick_rate, Instant::now());
 
     // Every time the user sends a message, broadcast it to
     // all other users...
@@ -124,64 +486,261 @@ async fn user_connected(ws: WebSocket, users: Users, game_state: GameState) {
                 break;
             }
         };
-        user_message(my_id, msg, &users, &game_state).await;
+        if let Err(kick) =
+            user_message(my_id, msg, &users, &game_state, &sessions, &input_queue, &mut limits)
+                .await
+        {
+            eprintln!("kicking [#{}]: {}", my_id, kick);
+            kicked(my_id, &users, &game_state).await;
+            break;
+        }
     }
 
     // user_ws_rx stream will keep processing as long as the user stays
     // connected. Once they disconnect, then...
-    user_disconnected(my_id, &users2).await;
+    user_disconnected(my_id, users2, game_state, sessions).await;
 }
 
-async fn user_message(my_id: usize, msg: Message, users: &Users, game_state: &GameState) {
-    // Skip any non-Text messages...
-    let msg = if let Ok(s) = msg.to_str() {
-        s
+async fn user_message(
+    my_id: usize,
+    msg: Message,
+    users: &Users,
+    game_state: &GameState,
+    sessions: &SharedSessions,
+    input_queue: &InputQueue,
+    limits: &mut Limits,
+) -> Result<(), Kick> {
+    // Skip any control messages...
+    let frame = if let Ok(s) = msg.to_str() {
+        communication::Frame::Text(s.to_string())
+    } else if msg.is_binary() {
+        communication::Frame::Binary(msg.as_bytes().to_vec())
     } else {
-        return;
+        return Ok(());
     };
-    if msg == "ping" {
-        let hello_message =
-            communication::ServerMessage::HelloPlayer(my_id, (*game_state.read().await).clone());
-        if let Err(_disconnected) = users
-            .read()
-            .await
-            .get(&my_id)
-            .expect(format!("user not found: [#{}]", my_id).as_str())
-            .send(Ok(Message::text(
-                to_string(&hello_message).expect(
-                    format!(
-                        "failed to serialize server hello message: {:#?}",
-                        hello_message
-                    )
-                    .as_str(),
-                ),
-            )))
-        {
-            // The tx is disconnected, our `user_disconnected` code
-            // should be happening in another task, nothing more to
-            // do here.
+    let message = match frame.decode::<communication::ClientMessage>() {
+        Ok(message) => message,
+        Err(e) => {
+            println!("error: failed to parse message from [#{}]: {}", my_id, e);
+            let reply = communication::ErrorReply::Malformed(e.to_string());
+            send_message(my_id, &communication::ServerMessage::Error(reply), users).await;
+            return Ok(());
+        }
+    };
+    let role = match users.read().await.get(&my_id) {
+        Some(user) => user.role,
+        None => return Ok(()),
+    };
+    match message {
+        communication::ClientMessage::Ping { timestamp } => {
+            send_message(my_id, &communication::ServerMessage::Pong { timestamp }, users).await;
+        }
+        communication::ClientMessage::Join {
+            name,
+            color,
+            protocol_version,
+        } => {
+            let reply = match user_joined(my_id, &name, color, protocol_version, game_state).await {
+                Ok(view) => {
+                    set_role(my_id, Role::Playing, users).await;
+                    // a resumed session keeps the token it was given
+                    let token = sessions
+                        .write()
+                        .await
+                        .tokens
+                        .entry(my_id)
+                        .or_insert_with(new_resume_token)
+                        .clone();
+                    communication::ServerMessage::HelloPlayer(my_id, Some(token), view)
+                }
+                Err(reason) => {
+                    println!("rejected [#{}] joining as {:?}: {}", my_id, name, reason);
+                    communication::ServerMessage::JoinRejected(reason)
+                }
+            };
+            send_message(my_id, &reply, users).await;
         }
-    } else if let Ok(message) = from_str::<communication::ClientMessage>(msg) {
-        game_state.write().await.handle_client_message(&message);
-        for (&_uid, tx) in users.read().await.iter() {
-            if let Err(_disconnected) = tx.send(Ok(Message::text(
-                to_string(&*game_state.read().await)
-                    .expect(format!("failed to serialize user message: {:#?}", message).as_str()),
-            ))) {
-                // The tx is disconnected, our `user_disconnected` code
-                // should be happening in another task, nothing more to
-                // do here.
+        communication::ClientMessage::Input(message) => {
+            if role != Role::Playing {
+                let reply = communication::ErrorReply::NotJoined;
+                send_message(my_id, &communication::ServerMessage::Error(reply), users).await;
+                return Ok(());
+            }
+            // the server knows whose socket this is, a client can only ever steer its own player
+            if message.impersonates(my_id) {
+                let reply = communication::ErrorReply::Impersonation;
+                send_message(my_id, &communication::ServerMessage::Error(reply), users).await;
+                return Err(Kick::Impersonation {
+                    claimed: message.player_handle,
+                });
+            }
+            let received = Instant::now();
+            if !limits.inputs.allow(received) {
+                println!("dropping input #{} from [#{}]: too many inputs", message.sequence, my_id);
+                return Ok(());
+            }
+            // applied at the start of the next tick, the result reaches everyone with the next broadcast
+            let queued = QueuedInput {
+                player: my_id,
+                received,
+                message,
+            };
+            if let Err(_closed) = input_queue.send(queued) {
+                eprintln!("game loop is gone, dropping input from [#{}]", my_id);
             }
         }
-    } else {
-        println!("error: failed to parse message: {:#?}", msg);
+        communication::ClientMessage::Ack(ack) => {
+            if let Some(user) = users.write().await.get_mut(&my_id) {
+                user.acknowledged = Some(ack.game_clock);
+            }
+        }
+        communication::ClientMessage::Leave => {
+            set_role(my_id, Role::Connected, users).await;
+            if game_state.read().await.players.contains_key(&my_id) {
+                remove_player(my_id, users, game_state).await;
+            }
+        }
+        communication::ClientMessage::Spectate(camera) => {
+            // watching and playing don't mix, the player leaves the board
+            if game_state.read().await.players.contains_key(&my_id) {
+                remove_player(my_id, users, game_state).await;
+            }
+            set_role(my_id, Role::Spectating(camera), users).await;
+            // nothing on the board to come back to, so no token either
+            sessions.write().await.tokens.remove(&my_id);
+            let hello_message = communication::ServerMessage::HelloPlayer(
+                my_id,
+                None,
+                game_state.read().await.view_from(camera),
+            );
+            send_message(my_id, &hello_message, users).await;
+        }
+        communication::ClientMessage::Chat(text) => {
+            let refused = if role != Role::Playing {
+                Some(communication::ErrorReply::NotJoined)
+            } else if text.chars().count() > config::MAX_CHAT_LENGTH {
+                Some(communication::ErrorReply::ChatTooLong)
+            } else if !limits.chat.allow(Instant::now()) {
+                Some(communication::ErrorReply::RateLimited)
+            } else {
+                None
+            };
+            if let Some(reply) = refused {
+                send_message(my_id, &communication::ServerMessage::Error(reply), users).await;
+                return Ok(());
+            }
+            let name = match game_state.read().await.players.get(&my_id) {
+                Some(player) => player.name.clone(),
+                None => return Ok(()),
+            };
+            let chat = communication::ServerMessage::Chat {
+                from: my_id,
+                name,
+                text,
+            };
+            broadcast_message(&chat, users).await;
+        }
+    }
+    Ok(())
+}
+
+/// Takes a misbehaving user off the board right away, without a grace period.
+async fn kicked(my_id: usize, users: &Users, game_state: &GameState) {
+    if let Some(user) = users.read().await.get(&my_id) {
+        if let Err(_disconnected) = user.tx.send(Ok(Message::close())) {}
+    }
+    if game_state.read().await.players.contains_key(&my_id) {
+        remove_player(my_id, users, game_state).await;
+    }
+}
+
+async fn set_role(my_id: usize, role: Role, users: &Users) {
+    if let Some(user) = users.write().await.get_mut(&my_id) {
+        // snapshots showed another part of the board before, no deltas against them
+        if user.role != role {
+            user.acknowledged = None;
+        }
+        user.role = role;
+    }
+}
+
+/// Adds the user's player to the game, returning what they can see of it.
+async fn user_joined(
+    my_id: usize,
+    name: &str,
+    color: rendering::Rgb,
+    protocol_version: u32,
+    game_state: &GameState,
+) -> Result<game::Game, communication::JoinRejection> {
+    if protocol_version != communication::PROTOCOL_VERSION {
+        return Err(communication::JoinRejection::IncompatibleVersion {
+            client: protocol_version,
+            server: communication::PROTOCOL_VERSION,
+        });
     }
-    // New message from this user, send it to everyone else (except same uid)...
+    let mut game = game_state.write().await;
+    // a resumed session already has its player
+    if !game.players.contains_key(&my_id) {
+        game.join(my_id, name, color)?;
+        println!("player ID {} joined as {:?}", my_id, name);
+    }
+    Ok(game.view_for(my_id))
 }
 
-async fn user_disconnected(my_id: usize, users: &Users) {
+async fn send_message(my_id: usize, message: &communication::ServerMessage, users: &Users) {
+    users
+        .read()
+        .await
+        .get(&my_id)
+        .expect(format!("user not found: [#{}]", my_id).as_str())
+        .send(message);
+}
+
+async fn user_disconnected(
+    my_id: usize,
+    users: Users,
+    game_state: GameState,
+    sessions: SharedSessions,
+) {
     eprintln!("good bye user: {}", my_id);
 
     // Stream closed up, so remove from the user list
     users.write().await.remove(&my_id);
+
+    // never joined, or already eaten, nothing left on the board
+    if !game_state.read().await.players.contains_key(&my_id) {
+        sessions.write().await.tokens.remove(&my_id);
+        return;
+    }
+
+    let grace_period =
+        Duration::from_secs_f32(game_state.read().await.config.disconnect_grace_period);
+    if grace_period == Duration::from_secs(0) {
+        sessions.write().await.tokens.remove(&my_id);
+        remove_player(my_id, &users, &game_state).await;
+        return;
+    }
+
+    // keep the player on the board for a while, so that they can reconnect
+    sessions.write().await.disconnected.insert(my_id, Instant::now());
+    tokio::task::spawn(async move {
+        tokio::time::delay_for(grace_period).await;
+        let expired = match sessions.read().await.disconnected.get(&my_id) {
+            Some(since) => since.elapsed() >= grace_period,
+            None => false,
+        };
+        if expired {
+            let mut sessions = sessions.write().await;
+            sessions.disconnected.remove(&my_id);
+            sessions.tokens.remove(&my_id);
+            drop(sessions);
+            remove_player(my_id, &users, &game_state).await;
+        }
+    });
+}
+
+async fn remove_player(my_id: usize, users: &Users, game_state: &GameState) {
+    println!("removing player ID: {}", my_id);
+    game_state.write().await.remove(&my_id);
+    broadcast_message(&communication::ServerMessage::PlayerLeft(my_id), users).await;
 }
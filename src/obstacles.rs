@@ -3,12 +3,15 @@ use quicksilver::{
     graphics::Color,
     Graphics,
 };
-use crate::rendering::Render;
+use crate::boundary::{BoundaryPolicy, Mobile};
+use crate::cell::CellId;
+use crate::rendering::{Render, Rgb};
 use itertools::Itertools;
 
 use crate::game::{PlayerHandle, VectorDef};
 
 pub type CollectibleHandle = PlayerHandle;
+pub type HazardHandle = PlayerHandle;
 
 use serde::{Deserialize, Serialize};
 
@@ -40,16 +43,20 @@ macro_rules! max {
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum CollisionBetween {
-    PlayerAndPlayer(PlayerHandle, PlayerHandle),
-    PlayerAndCollectible(PlayerHandle, CollectibleHandle),
+    PlayerAndPlayer(CellId, CellId),
+    PlayerAndCollectible(CellId, CollectibleHandle),
+    PlayerAndHazard(CellId, HazardHandle),
+    /// ejected mass flying into a hazard
+    CollectibleAndHazard(CollectibleHandle, HazardHandle),
 }
 
 pub trait Obstacle {
     fn strength(&self) -> f32;
     fn radius(&self) -> f32;
     fn center(&self) -> Vector;
-    fn collides(&self, other: &impl Obstacle) -> bool {
-        self.center().distance(other.center()) < max!(self.radius(), other.radius())
+    /// Whether either one covers the center of the other, across the edges when the board wraps around.
+    fn collides(&self, other: &impl Obstacle, boundary: BoundaryPolicy, game_size: Vector) -> bool {
+        boundary.distance(self.center(), other.center(), game_size) < max!(self.radius(), other.radius())
     }
 
     fn can_kill(&self, other: &impl Obstacle) -> bool {
@@ -63,15 +70,46 @@ pub struct Collectible {
     pub name: String,
     #[serde(with = "VectorDef")]
     pub position: Vector,
+    /// per second
     pub speed: f32,
     #[serde(with = "VectorDef")]
     pub direction: Vector,
     pub size: f32,
+    pub color: Rgb,
+    /// player who ejected this piece of mass, if any
+    pub ejected_by: Option<PlayerHandle>,
+    /// game clock from which the player who ejected it may eat it again
+    pub edible_at: u32,
+}
+
+impl Collectible {
+    pub fn edible_by(&self, player: PlayerHandle, game_clock: u32) -> bool {
+        self.ejected_by != Some(player) || game_clock >= self.edible_at
+    }
+
+    /// Moves by a single step `dt` seconds long, slowing down until it stops.
+    pub fn advance(&mut self, boundary: BoundaryPolicy, game_size: Vector, dt: f32) {
+        if self.speed <= 0. || self.direction.len2() == 0. {
+            return;
+        }
+        self.position += self.direction.normalize() * self.speed * dt;
+        self.speed *= crate::config::COLLECTIBLE_FRICTION.powf(dt);
+        if self.speed < crate::config::MIN_DRIFT_SPEED {
+            self.speed = 0.;
+        }
+        boundary.apply(self, game_size);
+    }
+}
+
+impl Collectible {
+    pub fn render_at(&self, gfx: &mut Graphics, position: Vector) {
+        gfx.fill_circle(&Circle::new(position, self.size as f32), self.color.into());
+    }
 }
 
 impl Render for Collectible {
     fn render(&self, gfx: &mut Graphics) {
-        gfx.fill_circle(&Circle::new(self.position, self.size as f32), Color::BLUE);
+        self.render_at(gfx, self.position);
     }
 }
 
@@ -87,6 +125,15 @@ impl Obstacle for Collectible {
     }
 }
 
+impl Mobile for Collectible {
+    fn position_mut(&mut self) -> &mut Vector {
+        &mut self.position
+    }
+    fn direction_mut(&mut self) -> &mut Vector {
+        &mut self.direction
+    }
+}
+
 impl PartialEq for Collectible {
     fn eq(&self, other: &Collectible) -> bool {
         self.handle == other.handle
@@ -94,3 +141,85 @@ impl PartialEq for Collectible {
 }
 
 impl Eq for Collectible {}
+
+/// A spiky obstacle, small cells pass under it while big ones pop into pieces.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Hazard {
+    pub handle: HazardHandle,
+    #[serde(with = "VectorDef")]
+    pub position: Vector,
+    /// per second
+    pub speed: f32,
+    #[serde(with = "VectorDef")]
+    pub direction: Vector,
+    pub size: f32,
+    /// pieces of ejected mass swallowed since the last shot
+    pub fed: u32,
+}
+
+impl Hazard {
+    /// Moves by a single step `dt` seconds long, a freshly shot hazard slows down until it stops.
+    pub fn advance(&mut self, boundary: BoundaryPolicy, game_size: Vector, dt: f32) {
+        if self.speed <= 0. || self.direction.len2() == 0. {
+            return;
+        }
+        self.position += self.direction.normalize() * self.speed * dt;
+        self.speed *= crate::config::COLLECTIBLE_FRICTION.powf(dt);
+        if self.speed < crate::config::MIN_DRIFT_SPEED {
+            self.speed = 0.;
+        }
+        boundary.apply(self, game_size);
+    }
+
+    /// Swallows a piece of ejected mass, returns `true` when it's time to shoot a new hazard.
+    pub fn feed(&mut self) -> bool {
+        self.fed += 1;
+        if self.fed < crate::config::HAZARD_FEED_LIMIT {
+            return false;
+        }
+        self.fed = 0;
+        true
+    }
+}
+
+impl Hazard {
+    pub fn render_at(&self, gfx: &mut Graphics, position: Vector) {
+        gfx.fill_circle(&Circle::new(position, self.size), Color::GREEN);
+        gfx.stroke_circle(&Circle::new(position, self.size), Color::BLACK);
+    }
+}
+
+impl Render for Hazard {
+    fn render(&self, gfx: &mut Graphics) {
+        self.render_at(gfx, self.position);
+    }
+}
+
+impl Obstacle for Hazard {
+    fn radius(&self) -> f32 {
+        self.size
+    }
+    fn center(&self) -> quicksilver::geom::Vector {
+        self.position
+    }
+    fn strength(&self) -> f32 {
+        self.size
+    }
+}
+
+impl Mobile for Hazard {
+    fn position_mut(&mut self) -> &mut Vector {
+        &mut self.position
+    }
+    fn direction_mut(&mut self) -> &mut Vector {
+        &mut self.direction
+    }
+}
+
+impl PartialEq for Hazard {
+    fn eq(&self, other: &Hazard) -> bool {
+        self.handle == other.handle
+    }
+}
+
+impl Eq for Hazard {}
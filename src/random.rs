@@ -0,0 +1,97 @@
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// Random number generator of the simulation.
+///
+/// Serialized together with the game, so that the same seed and the same inputs
+/// always produce the same world.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "GameRngState", into = "GameRngState")]
+pub struct GameRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+/// What is needed to restore a `GameRng` exactly where it left off.
+///
+/// The word position is 128 bits, split in halves as not every format has integers that big.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct GameRngState {
+    seed: u64,
+    word_pos_high: u64,
+    word_pos_low: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl From<GameRngState> for GameRng {
+    fn from(state: GameRngState) -> Self {
+        let mut game_rng = Self::new(state.seed);
+        game_rng
+            .rng
+            .set_word_pos((state.word_pos_high as u128) << 64 | state.word_pos_low as u128);
+        game_rng
+    }
+}
+
+impl From<GameRng> for GameRngState {
+    fn from(game_rng: GameRng) -> Self {
+        let word_pos = game_rng.rng.get_word_pos();
+        Self {
+            seed: game_rng.seed,
+            word_pos_high: (word_pos >> 64) as u64,
+            word_pos_low: word_pos as u64,
+        }
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod test_random {
+    use super::*;
+
+    #[test]
+    fn test_restores_where_it_left_off() {
+        let mut game_rng = GameRng::new(3);
+        game_rng.rng.set_word_pos(1 << 64 | 5);
+        let restored: GameRng = serde_json::from_str(&serde_json::to_string(&game_rng).unwrap()).unwrap();
+        assert_eq!(restored.rng.get_word_pos(), game_rng.rng.get_word_pos());
+        assert_eq!(restored.seed(), 3);
+        assert_eq!(restored.clone().next_u64(), game_rng.next_u64());
+    }
+}
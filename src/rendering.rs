@@ -1,7 +1,39 @@
 use quicksilver::{
+    graphics::Color,
     Graphics
 };
+use serde::{Deserialize, Serialize};
 
 pub trait Render {
     fn render(&self, gfx: &mut Graphics);
 }
+
+/// Serializable color of an entity.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const RED: Rgb = Rgb { r: 255, g: 0, b: 0 };
+    pub const BLUE: Rgb = Rgb { r: 0, g: 0, b: 255 };
+}
+
+impl Default for Rgb {
+    fn default() -> Self {
+        Self::RED
+    }
+}
+
+impl Into<Color> for Rgb {
+    fn into(self) -> Color {
+        Color {
+            r: self.r as f32 / 255.,
+            g: self.g as f32 / 255.,
+            b: self.b as f32 / 255.,
+            a: 1.,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+/// Keeps the simulation ticking at a fixed rate regardless of how long a single tick takes.
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    tick: Duration,
+    max_catch_up: u32,
+    next_tick: Instant,
+}
+
+/// Ticks due at a given moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DueTicks {
+    /// how many steps to run now
+    pub run: u32,
+    /// ticks skipped because the server fell too far behind
+    pub dropped: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: u64, max_catch_up: u32, start: Instant) -> Self {
+        debug_assert!(tick_rate > 0);
+        debug_assert!(max_catch_up > 0);
+        let tick = Duration::from_nanos(1_000_000_000 / tick_rate);
+        Self {
+            tick,
+            max_catch_up,
+            next_tick: start + tick,
+        }
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    /// When the next tick is due, the loop should sleep until then.
+    pub fn next_tick(&self) -> Instant {
+        self.next_tick
+    }
+
+    /// Counts the ticks due at `now` and moves the schedule past them.
+    ///
+    /// Never asks for more than `max_catch_up` steps at once, the rest is dropped
+    /// so that a long stall doesn't turn into a burst of simulation.
+    pub fn due(&mut self, now: Instant) -> DueTicks {
+        if now < self.next_tick {
+            return DueTicks { run: 0, dropped: 0 };
+        }
+        let behind = ((now - self.next_tick).as_nanos() / self.tick.as_nanos()) as u32 + 1;
+        if behind > self.max_catch_up {
+            self.next_tick = now + self.tick;
+            return DueTicks {
+                run: self.max_catch_up,
+                dropped: behind - self.max_catch_up,
+            };
+        }
+        self.next_tick += self.tick * behind;
+        DueTicks {
+            run: behind,
+            dropped: 0,
+        }
+    }
+}
+
+/// Token bucket limiting how often a single client can do something.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_second: f32,
+    burst: f32,
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32, burst: u32, start: Instant) -> Self {
+        Self {
+            per_second: per_second as f32,
+            burst: burst as f32,
+            tokens: burst as f32,
+            last_refill: start,
+        }
+    }
+
+    /// Takes a token if there is one left.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if now > self.last_refill {
+            let refilled = (now - self.last_refill).as_secs_f32() * self.per_second;
+            self.tokens = (self.tokens + refilled).min(self.burst);
+            self.last_refill = now;
+        }
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_scheduler {
+    use super::*;
+
+    #[test]
+    fn test_catch_up() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(100, 5, start);
+        let tick = timestep.tick();
+        assert_eq!(tick, Duration::from_millis(10));
+
+        assert_eq!(timestep.due(start), DueTicks { run: 0, dropped: 0 });
+        assert_eq!(timestep.due(start + tick), DueTicks { run: 1, dropped: 0 });
+        // a slow tick, the next ones run back to back
+        assert_eq!(timestep.due(start + tick * 4), DueTicks { run: 3, dropped: 0 });
+        assert_eq!(timestep.next_tick(), start + tick * 5);
+        // a stall, only `max_catch_up` steps are run
+        assert_eq!(timestep.due(start + tick * 20), DueTicks { run: 5, dropped: 11 });
+        assert_eq!(timestep.next_tick(), start + tick * 21);
+    }
+
+    #[test]
+    fn test_bursts_are_limited() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(10, 3, start);
+        let allowed = (0..10).filter(|_| limiter.allow(start)).count();
+        assert_eq!(allowed, 3);
+        assert!(!limiter.allow(start + Duration::from_millis(50)));
+        assert!(limiter.allow(start + Duration::from_millis(150)));
+        assert!(!limiter.allow(start + Duration::from_millis(150)));
+    }
+}
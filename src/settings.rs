@@ -0,0 +1,235 @@
+use crate::config::GameConfig;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+/// Environment variables starting with this override settings,
+/// `PRAWARIO_GAME_TICK_RATE=30` sets `game.tick_rate`.
+pub const ENV_PREFIX: &str = "PRAWARIO_";
+
+/// Where the server listens and what it serves besides the game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: IpAddr,
+    pub port: u16,
+    /// the built client, served to browsers
+    pub static_dir: PathBuf,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3030,
+            static_dir: PathBuf::from("dist/"),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+}
+
+/// Everything the backend can be configured with, laid out like the config file:
+///
+/// ```toml
+/// [server]
+/// port = 3030
+///
+/// [game]
+/// tick_rate = 30
+/// board_width = 5000.0
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub server: ServerConfig,
+    pub game: GameConfig,
+}
+
+/// Format of a config file, told apart by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(PathBuf, std::io::Error),
+    UnknownFormat(PathBuf),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    /// an override names a setting that doesn't exist
+    UnknownKey(String),
+    InvalidValue { key: String, value: String },
+    /// the settings parse, but the game can't be played with them
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io(path, e) => write!(f, "can't read {}: {}", path.display(), e),
+            SettingsError::UnknownFormat(path) => {
+                write!(f, "{} is neither a .toml nor a .json file", path.display())
+            }
+            SettingsError::Toml(e) => write!(f, "invalid toml config: {}", e),
+            SettingsError::Json(e) => write!(f, "invalid json config: {}", e),
+            SettingsError::UnknownKey(key) => write!(f, "unknown setting: {}", key),
+            SettingsError::InvalidValue { key, value } => {
+                write!(f, "invalid value for {}: {:?}", key, value)
+            }
+            SettingsError::Invalid(reason) => write!(f, "invalid settings: {}", reason),
+        }
+    }
+}
+
+impl Settings {
+    /// Settings from the file at `path`, then the environment, then `overrides`,
+    /// each one winning over the previous. Anything left unset keeps its default.
+    pub fn load(path: Option<&Path>, overrides: &[(String, String)]) -> Result<Self, SettingsError> {
+        let mut settings = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        settings.apply_env(std::env::vars())?;
+        for (key, value) in overrides {
+            settings.set(key, value)?;
+        }
+        settings.game.check().map_err(SettingsError::Invalid)?;
+        Ok(settings)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, SettingsError> {
+        let format = Format::of(path).ok_or_else(|| SettingsError::UnknownFormat(path.to_owned()))?;
+        let text =
+            std::fs::read_to_string(path).map_err(|e| SettingsError::Io(path.to_owned(), e))?;
+        Self::parse(&text, format)
+    }
+
+    pub fn parse(text: &str, format: Format) -> Result<Self, SettingsError> {
+        match format {
+            Format::Toml => toml::from_str(text).map_err(SettingsError::Toml),
+            Format::Json => serde_json::from_str(text).map_err(SettingsError::Json),
+        }
+    }
+
+    /// Overrides a single setting, named like in the config file: `server.port`, `game.tick_rate`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        let unknown = || SettingsError::UnknownKey(key.to_string());
+        let (section, name) = key.split_once('.').ok_or_else(unknown)?;
+        let mut tree = serde_json::to_value(&*self).map_err(SettingsError::Json)?;
+        let entry = tree
+            .get_mut(section)
+            .and_then(|section| section.get_mut(name))
+            .ok_or_else(unknown)?;
+        let invalid = || SettingsError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        // text settings take the value as it is, the rest parses it as a number or a boolean
+        *entry = if entry.is_string() {
+            serde_json::Value::String(value.to_string())
+        } else {
+            serde_json::from_str(value).map_err(|_e| invalid())?
+        };
+        *self = serde_json::from_value(tree).map_err(|_e| invalid())?;
+        Ok(())
+    }
+
+    /// Applies every `PRAWARIO_<SECTION>_<NAME>` variable among `vars`.
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), SettingsError> {
+        for (var, value) in vars {
+            let name = match var.strip_prefix(ENV_PREFIX) {
+                Some(name) => name.to_lowercase(),
+                None => continue,
+            };
+            let key = match name.split_once('_') {
+                Some((section, name)) => format!("{}.{}", section, name),
+                None => return Err(SettingsError::UnknownKey(var)),
+            };
+            self.set(&key, &value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_settings {
+    use super::*;
+
+    #[test]
+    fn test_missing_entries_keep_their_defaults() {
+        let text = "[server]\nport = 3030\n\n[game]\ntick_rate = 30\nboard_width = 5000.0\n";
+        let settings = Settings::parse(text, Format::Toml).expect("parses");
+        assert_eq!(settings.server.port, 3030);
+        assert_eq!(settings.server.static_dir, ServerConfig::default().static_dir);
+        assert_eq!(settings.game.tick_rate, 30);
+        assert_eq!(settings.game.board_width, 5000.);
+        assert_eq!(settings.game.board_height, crate::config::BOARD_HEIGHT);
+
+        let json = Settings::parse(r#"{"game":{"tick_rate":30}}"#, Format::Json).expect("parses");
+        assert_eq!(json.game.tick_rate, 30);
+        assert_eq!(json.server, ServerConfig::default());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut settings = Settings::default();
+        settings.set("server.bind", "127.0.0.1").expect("valid");
+        settings.set("server.static_dir", "1234").expect("valid");
+        settings.set("game.boundary", "Wrap").expect("valid");
+        settings.set("game.growth_factor", "0.5").expect("valid");
+        settings.set("game.disconnect_grace_period", "30").expect("valid");
+        assert_eq!(settings.server.address(), "127.0.0.1:3030".parse().unwrap());
+        assert_eq!(settings.server.static_dir, PathBuf::from("1234"));
+        assert_eq!(settings.game.boundary, crate::boundary::BoundaryPolicy::Wrap);
+        assert_eq!(settings.game.growth_factor, 0.5);
+        assert_eq!(settings.game.disconnect_grace_period, 30.);
+
+        let vars = vec![
+            ("PATH".to_string(), "/bin".to_string()),
+            ("PRAWARIO_GAME_HAZARD_COUNT".to_string(), "3".to_string()),
+            ("PRAWARIO_SERVER_PORT".to_string(), "8080".to_string()),
+        ];
+        settings.apply_env(vars).expect("valid");
+        assert_eq!(settings.game.hazard_count, 3);
+        assert_eq!(settings.server.port, 8080);
+    }
+
+    #[test]
+    fn test_bad_overrides_are_refused() {
+        let mut settings = Settings::default();
+        assert!(matches!(settings.set("game.gravity", "1"), Err(SettingsError::UnknownKey(_))));
+        assert!(matches!(settings.set("port", "1"), Err(SettingsError::UnknownKey(_))));
+        assert!(matches!(
+            settings.set("server.port", "eighty"),
+            Err(SettingsError::InvalidValue { .. })
+        ));
+        assert_eq!(settings, Settings::default());
+
+        settings.game.tick_rate = 0;
+        assert!(settings.game.check().is_err());
+        settings.game.tick_rate = crate::config::TICK_RATE;
+        settings.game.disconnect_grace_period = -1.;
+        assert!(settings.game.check().is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::communication::InputMessage;
+use crate::config::GameConfig;
+use crate::game::{Game, PlayerHandle, UserInput};
+use crate::random::GameRng;
+use crate::rendering::Rgb;
+use itertools::Itertools;
+use quicksilver::geom::Vector;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// How the board looks at a given moment, compared to tell whether a replay ended the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub game_clock: u32,
+    pub players: usize,
+    pub cells: usize,
+    pub collectibles: usize,
+    pub hazards: usize,
+    pub biggest_player: f32,
+    pub total_size: f32,
+}
+
+impl Stats {
+    pub fn of(game: &Game) -> Self {
+        // summed in a fixed order, floats add up differently in whatever order a map iterates in
+        let sizes = game
+            .players
+            .values()
+            .sorted_by_key(|player| player.handle)
+            .map(|player| player.total_size());
+        Self {
+            game_clock: game.game_clock,
+            players: game.players.len(),
+            cells: game.players.values().map(|player| player.cells().count()).sum(),
+            collectibles: game.collectibles.len(),
+            hazards: game.hazards.len(),
+            biggest_player: sizes.clone().fold(0., f32::max),
+            total_size: sizes.sum(),
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "game clock:     {}", self.game_clock)?;
+        writeln!(f, "players alive:  {}", self.players)?;
+        writeln!(f, "player cells:   {}", self.cells)?;
+        writeln!(f, "collectibles:   {}", self.collectibles)?;
+        writeln!(f, "hazards:        {}", self.hazards)?;
+        writeln!(f, "biggest player: {:.1}", self.biggest_player)?;
+        write!(f, "total size:     {:.1}", self.total_size)
+    }
+}
+
+/// An input applied right before the step at `game_clock`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub game_clock: u32,
+    pub player: PlayerHandle,
+    pub input: UserInput,
+}
+
+/// Everything needed to run a simulation again, step by step, along with how it ended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub seed: u64,
+    pub config: GameConfig,
+    /// bots join with handles `1..=players` before the first step
+    pub players: usize,
+    pub ticks: u32,
+    pub inputs: Vec<RecordedInput>,
+    pub stats: Stats,
+}
+
+/// Bots pick a new direction this often.
+const BOT_STEERING_INTERVAL: u32 = 30;
+
+fn join_bots(game: &mut Game, players: usize) {
+    for handle in 1..=players {
+        let color = Rgb {
+            r: (handle * 67 % 256) as u8,
+            g: (handle * 131 % 256) as u8,
+            b: (handle * 197 % 256) as u8,
+        };
+        game.join(handle, &format!("bot {}", handle), color)
+            .expect("bot names are valid and unique");
+    }
+}
+
+fn apply(game: &mut Game, input: &RecordedInput) {
+    let message = InputMessage::new(vec![(input.player, input.input)], input.player, 0);
+    game.handle_input_message(input.player, &message);
+}
+
+/// Runs `ticks` steps with `players` bots steering at random, without any server.
+pub fn simulate(seed: u64, config: GameConfig, players: usize, ticks: u32) -> Recording {
+    let mut game = Game::with_config(seed, config.clone());
+    join_bots(&mut game, players);
+    // the bots draw from their own generator, the game's stays as if they were real players
+    let mut bots = GameRng::new(seed.wrapping_add(1));
+    let mut inputs = vec![];
+    for _tick in 0..ticks {
+        if game.game_clock % BOT_STEERING_INTERVAL == 0 {
+            let mut alive: Vec<PlayerHandle> = game.players.keys().copied().collect();
+            alive.sort();
+            for player in alive {
+                let angle = bots.gen_range(0., std::f32::consts::PI * 2.);
+                let input = RecordedInput {
+                    game_clock: game.game_clock,
+                    player,
+                    input: UserInput::Steer(Vector::new(angle.cos(), angle.sin())),
+                };
+                apply(&mut game, &input);
+                inputs.push(input);
+            }
+        }
+        game.step();
+    }
+    Recording {
+        seed,
+        config,
+        players,
+        ticks,
+        inputs,
+        stats: Stats::of(&game),
+    }
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| format!("invalid recording {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string(self).expect("recordings always serialize");
+        std::fs::write(path, text).map_err(|e| format!("can't write {}: {}", path.display(), e))
+    }
+
+    /// Runs the recorded simulation again, returning how it ended this time.
+    pub fn replay(&self) -> Stats {
+        let mut game = Game::with_config(self.seed, self.config.clone());
+        join_bots(&mut game, self.players);
+        let mut inputs = self.inputs.iter().peekable();
+        for _tick in 0..self.ticks {
+            while let Some(input) = inputs.next_if(|input| input.game_clock <= game.game_clock) {
+                apply(&mut game, input);
+            }
+            game.step();
+        }
+        Stats::of(&game)
+    }
+}
+
+#[cfg(test)]
+mod test_simulation {
+    use super::*;
+
+    #[test]
+    fn test_replay_ends_like_the_recording() {
+        let recording = simulate(3, GameConfig::default(), 5, 300);
+        assert_eq!(recording.stats.game_clock, 300);
+        assert!(!recording.inputs.is_empty());
+
+        let saved = serde_json::to_string(&recording).unwrap();
+        let loaded: Recording = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded.replay(), recording.stats);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::game::{Camera, Game, Player, PlayerHandle};
+use crate::obstacles::{Collectible, CollectibleHandle, Hazard, HazardHandle};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// State update sent from the server to the clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Snapshot {
+    Full(Game),
+    Delta(GameDelta),
+}
+
+/// Difference between two snapshots of the game, keyed by entity handles.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GameDelta {
+    /// game clock of the snapshot this delta has to be applied on
+    pub baseline_clock: u32,
+    pub game_clock: u32,
+    /// players that were created or changed since the baseline
+    pub players: Vec<Player>,
+    pub removed_players: Vec<PlayerHandle>,
+    /// collectibles that were created or changed since the baseline
+    pub collectibles: Vec<Collectible>,
+    pub removed_collectibles: Vec<CollectibleHandle>,
+    pub hazards: Vec<Hazard>,
+    pub removed_hazards: Vec<HazardHandle>,
+}
+
+/// Entities that can tell whether they changed between two snapshots.
+pub trait Changed {
+    fn changed_since(&self, baseline: &Self) -> bool;
+}
+
+impl Changed for Player {
+    fn changed_since(&self, baseline: &Self) -> bool {
+        self.position != baseline.position
+            || self.direction != baseline.direction
+            || self.speed != baseline.speed
+            || self.size != baseline.size
+            || self.name != baseline.name
+            || self.color != baseline.color
+            || self.input_sequence != baseline.input_sequence
+            || self.input_clock != baseline.input_clock
+            || self.fragments != baseline.fragments
+    }
+}
+
+impl Changed for Collectible {
+    fn changed_since(&self, baseline: &Self) -> bool {
+        self.position != baseline.position
+            || self.direction != baseline.direction
+            || self.speed != baseline.speed
+            || self.size != baseline.size
+            || self.name != baseline.name
+    }
+}
+
+impl Changed for Hazard {
+    fn changed_since(&self, baseline: &Self) -> bool {
+        self.position != baseline.position
+            || self.direction != baseline.direction
+            || self.speed != baseline.speed
+            || self.size != baseline.size
+            || self.fed != baseline.fed
+    }
+}
+
+fn changed<T: Changed + Clone>(baseline: &HashMap<usize, T>, current: &HashMap<usize, T>) -> Vec<T> {
+    current
+        .iter()
+        .filter(|(handle, entity)| match baseline.get(handle) {
+            Some(old) => entity.changed_since(old),
+            None => true,
+        })
+        .map(|(_handle, entity)| entity.clone())
+        .collect()
+}
+
+fn removed<T>(baseline: &HashMap<usize, T>, current: &HashMap<usize, T>) -> Vec<usize> {
+    baseline
+        .keys()
+        .filter(|handle| !current.contains_key(handle))
+        .cloned()
+        .collect()
+}
+
+impl GameDelta {
+    pub fn between(baseline: &Game, current: &Game) -> Self {
+        Self {
+            baseline_clock: baseline.game_clock,
+            game_clock: current.game_clock,
+            players: changed(&baseline.players, &current.players),
+            removed_players: removed(&baseline.players, &current.players),
+            collectibles: changed(&baseline.collectibles, &current.collectibles),
+            removed_collectibles: removed(&baseline.collectibles, &current.collectibles),
+            hazards: changed(&baseline.hazards, &current.hazards),
+            removed_hazards: removed(&baseline.hazards, &current.hazards),
+        }
+    }
+
+    /// Builds the new snapshot, `None` if `baseline` is not the one this delta was made against.
+    pub fn apply(&self, baseline: &Game) -> Option<Game> {
+        if baseline.game_clock != self.baseline_clock {
+            return None;
+        }
+        let mut state = baseline.clone();
+        state.game_clock = self.game_clock;
+        for handle in &self.removed_players {
+            state.players.remove(handle);
+        }
+        for player in &self.players {
+            state.players.insert(player.handle, player.clone());
+        }
+        for handle in &self.removed_collectibles {
+            state.collectibles.remove(handle);
+        }
+        for collectible in &self.collectibles {
+            state.collectibles.insert(collectible.handle, collectible.clone());
+        }
+        for handle in &self.removed_hazards {
+            state.hazards.remove(handle);
+        }
+        for hazard in &self.hazards {
+            state.hazards.insert(hazard.handle, hazard.clone());
+        }
+        Some(state)
+    }
+}
+
+/// Recent snapshots, kept by the server to diff against and by the client to apply deltas on.
+#[derive(Debug, Clone)]
+pub struct SnapshotHistory {
+    capacity: usize,
+    snapshots: VecDeque<Game>,
+}
+
+impl Default for SnapshotHistory {
+    fn default() -> Self {
+        Self::new(crate::config::SNAPSHOT_HISTORY)
+    }
+}
+
+impl SnapshotHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, snapshot: Game) {
+        if self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn get(&self, game_clock: u32) -> Option<&'_ Game> {
+        self.snapshots
+            .iter()
+            .find(|snapshot| snapshot.game_clock == game_clock)
+    }
+
+    /// Picks a delta against the acknowledged snapshot if it's still known, full snapshot otherwise.
+    ///
+    /// Only the part of the game visible through `camera` is sent.
+    pub fn snapshot_for(
+        &self,
+        camera: Camera,
+        acknowledged: Option<u32>,
+        current: &Game,
+    ) -> Snapshot {
+        let view = current.view_from(camera);
+        match acknowledged.and_then(|game_clock| self.get(game_clock)) {
+            Some(baseline) => {
+                Snapshot::Delta(GameDelta::between(&baseline.view_from(camera), &view))
+            }
+            None => Snapshot::Full(view),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_snapshot {
+    use super::*;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let mut game = Game::new();
+        game.add(1);
+        game.add(2);
+        let baseline = game.clone();
+
+        game.remove(&2);
+        game.add(3);
+        game.handle_inputs(vec![(1, crate::game::UserInput::Right)]);
+        game.step();
+
+        let delta = GameDelta::between(&baseline, &game);
+        assert_eq!(delta.removed_players, vec![2]);
+        assert_eq!(delta.players.len(), 2);
+
+        let rebuilt = delta.apply(&baseline).expect("baseline matches");
+        assert_eq!(rebuilt.game_clock, game.game_clock);
+        assert_eq!(rebuilt.players.len(), game.players.len());
+        assert_eq!(rebuilt.players[&1].position, game.players[&1].position);
+        assert!(delta.apply(&game).is_none());
+    }
+
+    #[test]
+    fn test_unknown_baseline_sends_full_snapshot() {
+        let mut history = SnapshotHistory::new(2);
+        let mut game = Game::new();
+        for _i in 0..3 {
+            game.step();
+            history.push(game.clone());
+        }
+        assert!(history.get(1).is_none());
+        match history.snapshot_for(Camera::Follow(1), Some(1), &game) {
+            Snapshot::Full(_) => {}
+            Snapshot::Delta(_) => panic!("baseline should have been forgotten"),
+        }
+        match history.snapshot_for(Camera::Follow(1), Some(3), &game) {
+            Snapshot::Delta(delta) => assert_eq!(delta.baseline_clock, 3),
+            Snapshot::Full(_) => panic!("baseline is still known"),
+        }
+    }
+
+    #[test]
+    fn test_deltas_against_an_older_acknowledged_snapshot() {
+        let mut history = SnapshotHistory::new(8);
+        let mut server = Game::with_seed(1);
+        server.add(1);
+        let mut client = Game::default();
+        client.active_player = Some(1);
+
+        let mut acknowledged = None;
+        for tick in 1..=9 {
+            server.handle_inputs(vec![(1, crate::game::UserInput::Right)]);
+            server.step();
+            if tick % 3 != 0 {
+                continue;
+            }
+            let snapshot = history.snapshot_for(Camera::Follow(1), acknowledged, &server);
+            history.push(server.clone());
+            let applied = client.update_state(snapshot);
+            assert_eq!(applied, Some(server.game_clock));
+            // the acknowledgement of every later snapshot is still on its way
+            acknowledged = acknowledged.or(applied);
+        }
+        assert_eq!(client.players[&1].position, server.players[&1].position);
+    }
+}
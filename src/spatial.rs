@@ -0,0 +1,163 @@
+use crate::boundary::BoundaryPolicy;
+use crate::obstacles::Obstacle;
+use quicksilver::geom::Vector;
+use std::collections::HashMap;
+
+/// Uniform grid bucketing entities by the cell their center falls into.
+///
+/// Used to find collision candidates without comparing every pair of entities.
+/// Entities wider than a grid cell are kept aside and returned by every query,
+/// a single huge player doesn't make every query scan a block of buckets as big as itself.
+#[derive(Debug, Clone)]
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    /// entities with a radius above `cell_size`, few enough to be checked directly
+    large: Vec<usize>,
+    /// biggest radius of the bucketed entities since the last `clear`, at most `cell_size`
+    max_radius: f32,
+    /// size of the board when it wraps around, queries near an edge look across it too
+    wrap: Option<Vector>,
+}
+
+impl Default for SpatialGrid {
+    fn default() -> Self {
+        Self::new(crate::config::SPATIAL_CELL_SIZE)
+    }
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        debug_assert!(cell_size > 0.);
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            large: vec![],
+            max_radius: 0.,
+            wrap: None,
+        }
+    }
+
+    fn cell(&self, position: Vector) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    /// Follows the boundary of the board, entities near the edge of a wrapping board are next
+    /// to the ones near the opposite edge.
+    pub fn set_boundary(&mut self, boundary: BoundaryPolicy, game_size: Vector) {
+        self.wrap = match boundary {
+            BoundaryPolicy::Wrap => Some(game_size),
+            BoundaryPolicy::Clamp | BoundaryPolicy::Bounce => None,
+        };
+    }
+
+    /// Empties the grid, keeping the buckets allocated for the next step.
+    pub fn clear(&mut self) {
+        for bucket in self.cells.values_mut() {
+            bucket.clear();
+        }
+        self.large.clear();
+        self.max_radius = 0.;
+    }
+
+    pub fn insert(&mut self, handle: usize, obstacle: &impl Obstacle) {
+        if obstacle.radius() > self.cell_size {
+            self.large.push(handle);
+            return;
+        }
+        let cell = self.cell(obstacle.center());
+        self.cells.entry(cell).or_default().push(handle);
+        if obstacle.radius() > self.max_radius {
+            self.max_radius = obstacle.radius();
+        }
+    }
+
+    /// Handles of everything that may collide with a circle at `center`.
+    ///
+    /// Returns a superset, the exact check is still up to the caller.
+    pub fn query(&self, center: Vector, radius: f32) -> Vec<usize> {
+        let reach = Vector::ONE * radius.max(self.max_radius);
+        // on a wrapping board the area around `center` also continues past the opposite edges
+        let copies: Vec<Vector> = match self.wrap {
+            Some(game_size) => (-1..=1)
+                .flat_map(|x| (-1..=1).map(move |y| (x as f32, y as f32)))
+                .map(|(x, y)| center + Vector::new(x * game_size.x, y * game_size.y))
+                .filter(|copy| {
+                    copy.x + reach.x >= 0.
+                        && copy.y + reach.y >= 0.
+                        && copy.x - reach.x <= game_size.x
+                        && copy.y - reach.y <= game_size.y
+                })
+                .collect(),
+            None => vec![center],
+        };
+        let mut candidates = self.large.clone();
+        for copy in copies {
+            let (min_x, min_y) = self.cell(copy - reach);
+            let (max_x, max_y) = self.cell(copy + reach);
+            for x in min_x..=max_x {
+                for y in min_y..=max_y {
+                    if let Some(bucket) = self.cells.get(&(x, y)) {
+                        candidates.extend(bucket.iter().cloned());
+                    }
+                }
+            }
+        }
+        // buckets are filled in hash map order, sort to keep the simulation reproducible
+        candidates.sort_unstable();
+        // the copies may overlap on small boards
+        candidates.dedup();
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod test_spatial {
+    use super::*;
+    use crate::cell::Cell;
+
+    fn cell(x: f32, y: f32, size: f32) -> Cell {
+        Cell {
+            position: Vector::new(x, y),
+            size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_large_entities_are_always_candidates() {
+        let mut grid = SpatialGrid::new(100.);
+        grid.insert(0, &cell(0., 0., 2000.));
+        grid.insert(1, &cell(50., 50., 10.));
+        grid.insert(2, &cell(5000., 5000., 10.));
+        // the huge cell reaches far, without widening the queries around small ones
+        assert_eq!(grid.query(Vector::new(1500., 0.), 10.), vec![0]);
+        assert_eq!(grid.query(Vector::new(60., 60.), 10.), vec![0, 1]);
+        assert_eq!(grid.query(Vector::new(5000., 5000.), 10.), vec![0, 2]);
+
+        grid.clear();
+        grid.insert(3, &cell(50., 50., 10.));
+        assert_eq!(grid.query(Vector::new(5000., 5000.), 10.), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_queries_look_across_a_wrapping_edge() {
+        let mut grid = SpatialGrid::new(100.);
+        let game_size = Vector::new(1050., 1000.);
+        grid.insert(0, &cell(1045., 995., 10.));
+        grid.insert(1, &cell(860., 500., 10.));
+        assert_eq!(grid.query(Vector::new(5., 5.), 10.), Vec::<usize>::new());
+        assert_eq!(grid.query(Vector::new(-5., 500.), 200.), Vec::<usize>::new());
+
+        grid.set_boundary(BoundaryPolicy::Wrap, game_size);
+        assert_eq!(grid.query(Vector::new(5., 5.), 10.), vec![0]);
+        // copies are placed in board coordinates, the narrower last column doesn't throw them off
+        assert_eq!(grid.query(Vector::new(10., 500.), 200.), vec![1]);
+
+        grid.set_boundary(BoundaryPolicy::Bounce, game_size);
+        assert_eq!(grid.query(Vector::new(5., 5.), 10.), Vec::<usize>::new());
+    }
+}
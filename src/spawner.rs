@@ -0,0 +1,162 @@
+use crate::boundary::BoundaryPolicy;
+use crate::cell::Cell;
+use crate::config::GameConfig;
+use crate::random::GameRng;
+use crate::rendering::Rgb;
+use quicksilver::geom::Vector;
+use rand::Rng;
+
+/// Spawned collectibles keep at least this far from the edge of any cell.
+pub const SPAWN_CLEARANCE: f32 = 50.;
+/// Random spots tried for a new collectible before giving up until the next spawn.
+pub const SPAWN_ATTEMPTS: usize = 8;
+
+const PALETTE: [Rgb; 6] = [
+    Rgb { r: 0, g: 0, b: 255 },
+    Rgb { r: 0, g: 170, b: 255 },
+    Rgb { r: 0, g: 200, b: 120 },
+    Rgb { r: 255, g: 200, b: 0 },
+    Rgb { r: 255, g: 120, b: 0 },
+    Rgb { r: 200, g: 0, b: 255 },
+];
+
+/// Collectibles the board is kept stocked with, following the density but never above the cap.
+pub fn target_count(config: &GameConfig) -> usize {
+    let area = config.board_width * config.board_height / 1_000_000.;
+    ((area * config.collectible_density).round() as usize).min(config.max_collectibles)
+}
+
+/// How many collectibles to spawn at `game_clock` while `current` are on the board.
+///
+/// The empty board is filled in one go by the very first step, after that whatever
+/// players eat is refilled a batch at a time.
+pub fn due(config: &GameConfig, game_clock: u32, current: usize) -> usize {
+    let missing = target_count(config).saturating_sub(current);
+    let interval = config.ticks(config.collectible_spawn_interval);
+    if game_clock == 0 {
+        missing
+    } else if game_clock % interval == 1 % interval {
+        missing.min(config.collectible_spawn_batch)
+    } else {
+        0
+    }
+}
+
+/// A random spot at least `SPAWN_CLEARANCE` away from every cell, if one turns up.
+///
+/// On a wrapping board the distance is measured across the edges too.
+pub fn free_spot(
+    rng: &mut GameRng,
+    boundary: BoundaryPolicy,
+    game_size: Vector,
+    cells: &[Cell],
+) -> Option<Vector> {
+    (0..SPAWN_ATTEMPTS)
+        .map(|_attempt| {
+            Vector::new(
+                rng.gen_range(0.0, game_size.x),
+                rng.gen_range(0.0, game_size.y),
+            )
+        })
+        .find(|spot| {
+            cells
+                .iter()
+                .all(|cell| {
+                    boundary.distance(cell.position, *spot, game_size) > cell.size + SPAWN_CLEARANCE
+                })
+        })
+}
+
+/// Size and color of a new collectible, bigger ones are worth more.
+pub fn random_looks(rng: &mut GameRng, config: &GameConfig) -> (f32, Rgb) {
+    let size = if config.collectible_max_size > config.collectible_min_size {
+        rng.gen_range(config.collectible_min_size, config.collectible_max_size)
+    } else {
+        config.collectible_min_size
+    };
+    (size, PALETTE[rng.gen_range(0, PALETTE.len())])
+}
+
+#[cfg(test)]
+mod test_spawner {
+    use super::*;
+
+    #[test]
+    fn test_density_and_cap() {
+        let mut config = GameConfig {
+            board_width: 2000.,
+            board_height: 1000.,
+            collectible_density: 50.,
+            max_collectibles: 1000,
+            collectible_spawn_interval: 0.5,
+            collectible_spawn_batch: 5,
+            ..GameConfig::default()
+        };
+        assert_eq!(target_count(&config), 100);
+        // a burst at the start, then batches
+        assert_eq!(due(&config, 0, 0), 100);
+        // half a second is 30 ticks at the default tick rate
+        assert_eq!(due(&config, 31, 0), 5);
+        assert_eq!(due(&config, 31, 97), 3);
+        assert_eq!(due(&config, 32, 0), 0);
+        assert_eq!(due(&config, 61, 100), 0);
+        // ejected mass may go over the target, nothing spawns then
+        assert_eq!(due(&config, 61, 150), 0);
+
+        config.max_collectibles = 40;
+        assert_eq!(due(&config, 0, 0), 40);
+    }
+
+    #[test]
+    fn test_no_spawns_inside_cells() {
+        let mut rng = GameRng::new(1);
+        let board = Vector::new(1000., 1000.);
+        let everywhere = Cell {
+            position: Vector::new(500., 500.),
+            size: 1000.,
+            ..Default::default()
+        };
+        assert_eq!(free_spot(&mut rng, BoundaryPolicy::Clamp, board, &[everywhere]), None);
+
+        let cell = Cell {
+            position: Vector::new(500., 500.),
+            size: 300.,
+            ..Default::default()
+        };
+        for _i in 0..100 {
+            if let Some(spot) = free_spot(&mut rng, BoundaryPolicy::Clamp, board, &[cell.clone()]) {
+                assert!(spot.distance(cell.position) > cell.size + SPAWN_CLEARANCE);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_spawns_inside_cells_across_the_edge() {
+        let mut rng = GameRng::new(1);
+        let board = Vector::new(1000., 1000.);
+        // reaches over the left edge, onto the right side of a wrapping board
+        let cell = Cell {
+            position: Vector::new(0., 500.),
+            size: 300.,
+            ..Default::default()
+        };
+        let across = Vector::new(1000., 500.);
+        for _i in 0..100 {
+            if let Some(spot) = free_spot(&mut rng, BoundaryPolicy::Wrap, board, &[cell.clone()]) {
+                assert!(spot.distance(across) > cell.size + SPAWN_CLEARANCE);
+            }
+        }
+    }
+
+    #[test]
+    fn test_looks_vary_within_bounds() {
+        let mut rng = GameRng::new(1);
+        let config = GameConfig::default();
+        let looks: Vec<(f32, Rgb)> = (0..50).map(|_i| random_looks(&mut rng, &config)).collect();
+        assert!(looks.iter().all(|(size, _color)| {
+            *size >= config.collectible_min_size && *size <= config.collectible_max_size
+        }));
+        assert!(looks.iter().any(|(size, _color)| *size != looks[0].0));
+        assert!(looks.iter().any(|(_size, color)| *color != looks[0].1));
+    }
+}